@@ -0,0 +1,3 @@
+fn main() {
+    message_registry::register_messages();
+}
@@ -1,5 +1,8 @@
 use message_proc_macros::Message;
 
+// lets message_list! include every message here with application_messages::*
+include!(concat!(env!("OUT_DIR"), "/message_registry.rs"));
+
 #[derive(Message, Debug)]
 #[pt_response(winit::window::WindowId)]
 pub struct OpenWindow {
@@ -11,6 +14,7 @@ pub struct OpenWindow {
 
 #[derive(Message, Debug)]
 #[pt_response(())]
+#[pt_priority(high)]
 pub struct CloseWindow {
     pub window: winit::window::WindowId,
 }
@@ -18,12 +22,15 @@ pub struct CloseWindow {
 
 #[derive(Message, Debug)]
 #[pt_response(())]
+#[pt_priority(high)]
 pub struct ExitProgram {
     pub code: u8,
 }
 
 
+// Only sent once the window's event loop is running, so nothing can wait for it during init
 #[derive(Message, Debug, Clone)]
+#[pt_not_during_init]
 pub struct KeyPress {
     pub key: winit::event::VirtualKeyCode,
     pub state: winit::event::ElementState,
@@ -1,16 +1,13 @@
-use std::{thread::JoinHandle, collections::HashMap, cell::{Cell, OnceCell}};
+use std::{thread::JoinHandle, collections::HashMap, cell::Cell};
 
-use futures::{FutureExt, future::LocalBoxFuture};
+use futures::FutureExt;
 use handler_proc_macros::Handler;
-use handler_structs::{HandlerInit, Handle};
+use handler_structs::{HandlerInit, Handle, HandleMut, HandlerShutdown};
 use message_list::C;
 
 
 use application_messages::*;
-use shared_future::SharedFuture;
-use winit::{event_loop::{EventLoop, EventLoopBuilder}, platform::wayland::EventLoopBuilderExtWayland, event};
-
-mod shared_future;
+use winit::{event_loop::{EventLoop, EventLoopBuilder}, platform::wayland::EventLoopBuilderExtWayland};
 
 #[derive(Debug)]
 enum WinitEvent {
@@ -20,25 +17,31 @@ enum WinitEvent {
 
 struct EventLoopData {
     event_loop_proxy: winit::event_loop::EventLoopProxy<WinitEvent>,
-    event_loop_join_handle: JoinHandle<()>,
+    _event_loop_join_handle: JoinHandle<()>,
 }
 
+// Serial, so the event loop is started by the first message and the others wait for it. OpenWindow
+// keeps it locked until the window is open, which is fine as the event loop only posts to the
+// context and never waits for it.
 #[derive(Handler)]
 #[pt_handles(OpenWindow, CloseWindow)]
+#[pt_serial]
 pub struct Windows {
-    event_loop_data: OnceCell<SharedFuture<EventLoopData>>,
+    event_loop_data: Option<EventLoopData>,
 }
 
 impl HandlerInit for Windows {
-    fn init<'a, Ctx: C + 'a>(_ctx: &Self::InitCtx<'a, Ctx>, _config: Self::InitConfig) -> Self {
-        Self {event_loop_data: OnceCell::new()}
+    type InitResult = Self;
+
+    async fn init<'a, Ctx: C + 'a>(_ctx: &'a Self::InitCtx<'a, Ctx>, _config: Self::InitConfig) -> Self {
+        Self {event_loop_data: None}
     }
 }
 
-impl Handle<OpenWindow> for Windows {
-    fn handle<'a>(&'a self, ctx: &'a impl C, message: OpenWindow) -> <OpenWindow as message_structs::Message>::Response<'a> {
+impl HandleMut<OpenWindow> for Windows {
+    fn handle_mut<'a>(&'a mut self, ctx: &'a impl C, message: OpenWindow) -> <OpenWindow as message_structs::Message>::Response<'a> {
         async move {
-            let event_loop_data = self.event_loop_data.get_or_init(|| Self::make_event_loop_data(ctx)).clone().await;
+            let event_loop_data = self.event_loop_data(ctx).await;
 
             let (sender, receiver) = smol::channel::bounded(1);
             event_loop_data.event_loop_proxy.send_event(WinitEvent::OpenWindow((message, sender))).unwrap();
@@ -47,10 +50,10 @@ impl Handle<OpenWindow> for Windows {
     }
 }
 
-impl Handle<CloseWindow> for Windows {
-    fn handle<'a>(&'a self, ctx: &'a impl C, message: CloseWindow) -> <CloseWindow as message_structs::Message>::Response<'a> {
+impl HandleMut<CloseWindow> for Windows {
+    fn handle_mut<'a>(&'a mut self, ctx: &'a impl C, message: CloseWindow) -> <CloseWindow as message_structs::Message>::Response<'a> {
         async move {
-            let event_loop_data = self.event_loop_data.get_or_init(|| Self::make_event_loop_data(ctx)).clone().await;
+            let event_loop_data = self.event_loop_data(ctx).await;
 
             event_loop_data.event_loop_proxy.send_event(WinitEvent::CloseWindow(message)).unwrap();
         }.boxed_local()
@@ -67,14 +70,15 @@ impl Windows {
                 winit::event::Event::WindowEvent { event, window_id } => {
                     match event {
                         winit::event::WindowEvent::CloseRequested => {
-                            drop(ctx_proxy.handle(CloseWindow{window: window_id}));
+                            // queued straight away, the event loop doesn't wait for the handlers. Fails if the
+                            // context has already quit, then there's no one to tell.
+                            let _ = ctx_proxy.post(CloseWindow{window: window_id});
                         },
                         winit::event::WindowEvent::KeyboardInput {input, ..} => {
                             let virtual_keycode = input.virtual_keycode;
                             let state = input.state;
                             if let Some(virtual_keycode) = virtual_keycode {
-                                // don't want to wait for the handlers to finish so drop the future
-                                drop(ctx_proxy.handle(KeyPress{key: virtual_keycode, state}));
+                                let _ = ctx_proxy.post(KeyPress{key: virtual_keycode, state});
                             }
                         },
                         _ => (),
@@ -102,40 +106,63 @@ impl Windows {
         })
     }
 
-    fn make_event_loop_data(ctx: &impl C) -> SharedFuture<EventLoopData> {
+    async fn event_loop_data(&mut self, ctx: &impl C) -> &EventLoopData {
+        if self.event_loop_data.is_none() {
+            self.event_loop_data = Some(Self::start_event_loop(ctx).await);
+        }
+        self.event_loop_data.as_ref().unwrap()
+    }
+
+    async fn start_event_loop(ctx: &impl C) -> EventLoopData {
         let ctx_proxy = ctx.proxy();
-        let fut = async {
-            let (sender, receiver) = smol::channel::bounded(1);
-            let event_loop_join_handle = std::thread::spawn(move || {
-                let event_loop = EventLoopBuilder::<WinitEvent>::with_user_event()
-                    .with_any_thread(true)
-                    .build();
-                
-                let event_loop_proxy = event_loop.create_proxy();
-                sender.send_blocking(event_loop_proxy).unwrap();
-                Self::run(event_loop, ctx_proxy);
-            });
-
-            let event_loop_proxy = receiver.recv().await.unwrap();
-            EventLoopData{event_loop_proxy, event_loop_join_handle}
-        };
-        SharedFuture::new(fut.boxed_local())
+        let (sender, receiver) = smol::channel::bounded(1);
+        let event_loop_join_handle = std::thread::spawn(move || {
+            let event_loop = EventLoopBuilder::<WinitEvent>::with_user_event()
+                .with_any_thread(true)
+                .build();
+            
+            let event_loop_proxy = event_loop.create_proxy();
+            sender.send_blocking(event_loop_proxy).unwrap();
+            Self::run(event_loop, ctx_proxy);
+        });
+
+        let event_loop_proxy = receiver.recv().await.unwrap();
+        EventLoopData{event_loop_proxy, _event_loop_join_handle: event_loop_join_handle}
     }
 }
 
+// Quits the context and exits once the other handlers have shut down. It has to be the first
+// handler so it is shut down last.
 #[derive(Handler)]
 #[pt_handles(ExitProgram)]
-pub struct ExitHandler {}
+#[pt_shutdown]
+pub struct ExitHandler {
+    code: Cell<Option<u8>>,
+}
 
 impl HandlerInit for ExitHandler {
-    fn init<'a, Ctx: C + 'a>(_ctx: &Self::InitCtx<'a, Ctx>, _config: Self::InitConfig) -> Self {
-        Self {}
+    type InitResult = Self;
+
+    async fn init<'a, Ctx: C + 'a>(_ctx: &'a Self::InitCtx<'a, Ctx>, _config: Self::InitConfig) -> Self {
+        Self {code: Cell::new(None)}
     }
 }
 
 
 impl Handle<ExitProgram> for ExitHandler {
-    fn handle<'a>(&'a self, _ctx: &'a impl C, message: ExitProgram) -> <ExitProgram as message_structs::Message>::Response<'a> {
-        std::process::exit(message.code as i32);
+    fn handle<'a>(&'a self, ctx: &'a impl C, message: ExitProgram) -> <ExitProgram as message_structs::Message>::Response<'a> {
+        self.code.set(Some(message.code));
+        ctx.quit();
+        async {}.boxed_local()
     }
-}
\ No newline at end of file
+}
+
+impl HandlerShutdown for ExitHandler {
+    fn shutdown<'a>(&'a self, _ctx: &'a impl C) -> futures::future::LocalBoxFuture<'a, ()> {
+        async move {
+            if let Some(code) = self.code.get() {
+                std::process::exit(code as i32);
+            }
+        }.boxed_local()
+    }
+}
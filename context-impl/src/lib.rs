@@ -1,21 +1,25 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 
 use handler_structs::HandlerSpec;
-use message_structs::MessageSpec;
+use message_structs::{MessageSpec, MessageId, Priority};
 use proc_macro2::{TokenStream, Ident, Span};
-use quote::quote;
+use quote::{quote, format_ident};
 use syn::{TypePath, parse_str, Expr};
 
+mod schema;
+
 struct Handler<'a> {
     spec: &'a HandlerSpec,
     member_name: Ident,
     get_member_expr: Expr,
     type_name: TypePath,
+    // the type of the handler's field in the context, serial handlers are behind a mutex
+    member_type: TokenStream,
 }
 
 impl<'a> Handler<'a> {
     fn handles(&self, message_spec: &MessageSpec) -> bool {
-        self.spec.handled_messages.iter().any(|spec| &spec.name == &message_spec.name)
+        self.spec.handled_messages.iter().any(|spec| spec.id == message_spec.id)
     }
 
     fn from_handler_spec(handler_spec: &'a HandlerSpec, name: &str) -> Self {
@@ -24,31 +28,306 @@ impl<'a> Handler<'a> {
         // type name comes from handler_spec.name
         let member_name = Ident::new(name, Span::call_site());
         let get_member_expr = parse_str(&format!("self.{}", member_name)).unwrap();
-        let type_name: TypePath = parse_str(&handler_spec.name).unwrap();
+        let type_name: TypePath = parse_str(handler_spec.name).unwrap();
+        let member_type = if handler_spec.is_serial {
+            quote!(::smol::lock::Mutex<#type_name>)
+        } else {
+            quote!(#type_name)
+        };
 
         Self {
             spec: handler_spec,
             member_name,
             get_member_expr,
             type_name,
+            member_type,
         }
     }
 }
 
-fn make_handle_impl_body(message_spec: &MessageSpec, handlers: &[&Handler], unwrap_member: bool) -> syn::Result<TokenStream> {
-    let get_member_expr = if unwrap_member {
-        |handler: &Handler<'_>| {
+// Requests that can be sent when no handler handles them. Whichever handlers there are are asked.
+fn can_have_no_handlers(message_spec: &MessageSpec) -> bool {
+    message_spec.is_collect || message_spec.is_first_match || message_spec.is_optional
+}
+
+// The message argument for each handler. The last handler gets the message itself, the others get
+// clones, which for shared messages only clones the Arc.
+fn message_args(handler_count: usize) -> Vec<TokenStream> {
+    (0..handler_count)
+        .map(|i| if i + 1 == handler_count { quote!(message) } else { quote!(message.clone()) })
+        .collect()
+}
+
+// Awaits call, made with the handler bound to handler, on the serial handler in the mutex at
+// member_expr. It's locked for the whole call, so its messages are handled one at a time, and
+// panics rather than deadlocks if the call sends it a message, see context_structs::serial.
+fn serial_call(handler: &Handler, member_expr: TokenStream, call: TokenStream) -> TokenStream {
+    let handler_type = &handler.type_name;
+    let name = handler.spec.name;
+    quote!({
+        let serial: &::smol::lock::Mutex<#handler_type> = &#member_expr;
+        let mut handler = ::context_structs::serial::lock(serial, #name).await;
+        ::context_structs::serial::polling(serial, #call).await
+    })
+}
+
+// The call of one handler for a message
+fn handler_call(handler: &Handler, message_name: &TypePath, member_expr: TokenStream, message_arg: TokenStream) -> TokenStream {
+    let handler_type = &handler.type_name;
+    if handler.spec.is_serial {
+        let call = serial_call(handler, quote!(*serial), quote!(< #handler_type as ::handler_structs::HandleMut::<#message_name> >::handle_mut(&mut *handler, self, message)));
+        quote!({
+            let message = #message_arg;
+            let serial: &::smol::lock::Mutex<#handler_type> = &#member_expr;
+            ::futures::FutureExt::boxed_local(async move #call)
+        })
+    } else {
+        quote!(< #handler_type as ::handler_structs::Handle::<#message_name> >::handle(&#member_expr, self, #message_arg))
+    }
+}
+
+// A statement using the response of each handler. The partial context skips the handlers that
+// haven't been initialised, so the shutdown hooks run when init fails can still send messages that
+// can go to any number of handlers.
+fn handler_statements(handlers: &[&Handler], message_name: &TypePath, unwrap_member: bool, statement: impl Fn(TokenStream) -> TokenStream) -> Vec<TokenStream> {
+    handlers.iter()
+        .zip(message_args(handlers.len()))
+        .map(|(handler, message_arg)| {
             let bare_expr = &handler.get_member_expr;
-            quote!(#bare_expr.as_ref().unwrap())
+            if unwrap_member {
+                let statement = statement(handler_call(handler, message_name, quote!(*handler), message_arg));
+                quote!(
+                    if let ::std::option::Option::Some(handler) = &#bare_expr {
+                        #statement
+                    }
+                )
+            } else {
+                statement(handler_call(handler, message_name, quote!(#bare_expr), message_arg))
+            }
+        })
+        .collect()
+}
+
+// The order the handlers are initialised in. Each handler comes after every handler that answers
+// one of its init requests, including all the handlers of a collect, first match or optional
+// request. Otherwise they keep the order they are listed in.
+fn init_order<'a, 'b>(handlers: &'b [Handler<'a>]) -> syn::Result<Vec<&'b Handler<'a>>> {
+    // for each handler, the handlers it needs and the request it needs them for
+    let dependencies: Vec<Vec<(usize, &MessageSpec)>> = handlers.iter()
+        .map(|handler| {
+            handler.spec.init_requests.iter()
+                .flat_map(|request| {
+                    handlers.iter()
+                        .enumerate()
+                        .filter(|(_, other)| other.handles(request))
+                        .map(|(index, _)| (index, *request))
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut initialised = vec![false; handlers.len()];
+    let mut order = Vec::with_capacity(handlers.len());
+    while order.len() < handlers.len() {
+        let next = (0..handlers.len())
+            .find(|&index| !initialised[index] && dependencies[index].iter().all(|(dependency, _)| initialised[*dependency]));
+
+        match next {
+            Some(index) => {
+                initialised[index] = true;
+                order.push(&handlers[index]);
+            },
+            None => return Err(init_cycle_error(handlers, &dependencies, &initialised)),
+        }
+    }
+    Ok(order)
+}
+
+// Every handler that is left needs another one that is left, so following them leads round a cycle.
+fn init_cycle_error(handlers: &[Handler], dependencies: &[Vec<(usize, &MessageSpec)>], initialised: &[bool]) -> syn::Error {
+    let mut path = vec![initialised.iter().position(|done| !done).unwrap()];
+    let mut requests = Vec::new();
+    loop {
+        let (dependency, request) = dependencies[*path.last().unwrap()].iter()
+            .find(|(dependency, _)| !initialised[*dependency])
+            .unwrap();
+        requests.push(*request);
+        if let Some(start) = path.iter().position(|index| index == dependency) {
+            path.push(*dependency);
+            let steps = path[start..].windows(2)
+                .zip(&requests[start..])
+                .map(|(pair, request)| format!("{} needs {} from {}", handlers[pair[0]].member_name, request.name, handlers[pair[1]].member_name))
+                .collect::<Vec<_>>();
+            return syn::Error::new(
+                handlers[path[start]].spec.span,
+                format!("Handlers depend on each other during init: {}", steps.join(", "))
+            );
         }
+        path.push(*dependency);
+    }
+}
+
+// Groups the handlers so that each one comes after the handlers that answer its init requests.
+// The handlers in a wave don't depend on each other, so they can be initialised concurrently.
+fn init_waves<'a, 'b>(handlers: &[&'b Handler<'a>]) -> Vec<Vec<&'b Handler<'a>>> {
+    let mut waves: Vec<usize> = Vec::with_capacity(handlers.len());
+    for (index, handler) in handlers.iter().enumerate() {
+        let wave = handlers[..index].iter()
+            .zip(&waves)
+            .filter(|(earlier, _)| handler.spec.init_requests.iter().any(|request| earlier.handles(request)))
+            .map(|(_, wave)| wave + 1)
+            .max()
+            .unwrap_or(0);
+        waves.push(wave);
+    }
+
+    let wave_count = waves.iter().max().map_or(0, |max| max + 1);
+    (0..wave_count)
+        .map(|wave| handlers.iter().zip(&waves).filter(|(_, w)| **w == wave).map(|(handler, _)| *handler).collect())
+        .collect()
+}
+
+fn make_handle_impl_body(message_spec: &MessageSpec, handlers: &[&Handler], unwrap_member: bool) -> syn::Result<TokenStream> {
+    let body = make_handle_impl_body_inner(message_spec, handlers, unwrap_member)?;
+    if message_spec.is_shared {
+        Ok(quote!(
+            let message = ::std::sync::Arc::new(message);
+            #body
+        ))
     } else {
-        |handler: &Handler<'_>| {
-            let bare_expr = &handler.get_member_expr;
+        Ok(body)
+    }
+}
+
+fn make_handle_impl_body_inner(message_spec: &MessageSpec, handlers: &[&Handler], unwrap_member: bool) -> syn::Result<TokenStream> {
+    let get_member_expr = |handler: &Handler<'_>| {
+        let bare_expr = &handler.get_member_expr;
+        if unwrap_member {
+            let error = format!("Handler {} wasn't initialised, so it can't handle {}. Use try_handle in shutdown hooks run when init fails.", handler.member_name, message_spec.name);
+            quote!(#bare_expr.as_ref().unwrap_or_else(|| panic!("{}", #error)))
+        } else {
             quote!(#bare_expr)
         }
     };
 
     let message_name: TypePath = parse_str(message_spec.name)?;
+
+    // every handler is asked, in the order they are listed, and their responses combined
+    // the responses, or the futures of async messages
+    let collect_responses = || {
+        let pushes = handler_statements(handlers, &message_name, unwrap_member, |call| quote!(responses.push(#call);));
+        quote!(
+            let _ = &message; // unused if there are no handlers
+            #[allow(unused_mut)]
+            let mut responses = ::std::vec::Vec::<<#message_name as ::message_structs::Message>::HandlerResponse<'_>>::new();
+            #(#pushes)*
+        )
+    };
+
+    if message_spec.is_collect {
+        let collect_responses = collect_responses();
+        let collect = quote!(<<#message_name as ::message_structs::CollectMessage>::Collector as ::message_structs::Collect<_>>::collect);
+        return Ok(if message_spec.is_async {
+            quote!(
+                use ::futures::FutureExt;
+                #collect_responses
+                let responses = ::futures::future::join_all(responses);
+                async move {
+                    #collect(responses.await)
+                }.boxed_local()
+            )
+        } else {
+            quote!(
+                #collect_responses
+                #collect(responses)
+            )
+        });
+    }
+
+    // the handlers are asked in the order they are listed, the first Some is the response
+    if message_spec.is_first_match {
+        let is_async = message_spec.is_async;
+        let asks = handler_statements(handlers, &message_name, unwrap_member, |call| {
+            let response = if is_async { quote!(#call.await) } else { call };
+            quote!(
+                if let ::std::option::Option::Some(response) = #response {
+                    return ::std::option::Option::Some(response);
+                }
+            )
+        });
+        return Ok(if is_async {
+            quote!(
+                use ::futures::FutureExt;
+                let _ = &message; // unused if there are no handlers
+                async move {
+                    #(#asks)*
+                    ::std::option::Option::None
+                }.boxed_local()
+            )
+        } else {
+            quote!(
+                let _ = &message;
+                #(#asks)*
+                ::std::option::Option::None
+            )
+        });
+    }
+
+    if message_spec.is_optional {
+        return Ok(match handlers {
+            [] if message_spec.is_async => quote!(
+                use ::futures::FutureExt;
+                let _ = message;
+                async move {
+                    ::std::option::Option::None
+                }.boxed_local()
+            ),
+            [] => quote!(
+                let _ = message;
+                ::std::option::Option::None
+            ),
+            // None if the partial context doesn't have the handler
+            [handler] if unwrap_member => {
+                let bare_expr = &handler.get_member_expr;
+                let response = handler_call(handler, &message_name, quote!(*handler), quote!(message));
+                if message_spec.is_async {
+                    quote!(
+                        use ::futures::FutureExt;
+                        match &#bare_expr {
+                            ::std::option::Option::Some(handler) => #response.map(::std::option::Option::Some).boxed_local(),
+                            ::std::option::Option::None => async move { ::std::option::Option::None }.boxed_local(),
+                        }
+                    )
+                } else {
+                    quote!(
+                        match &#bare_expr {
+                            ::std::option::Option::Some(handler) => ::std::option::Option::Some(#response),
+                            ::std::option::Option::None => ::std::option::Option::None,
+                        }
+                    )
+                }
+            },
+            [handler] => {
+                let response = handler_call(handler, &message_name, get_member_expr(handler), quote!(message));
+                if message_spec.is_async {
+                    quote!(
+                        use ::futures::FutureExt;
+                        #response.map(::std::option::Option::Some).boxed_local()
+                    )
+                } else {
+                    quote!(::std::option::Option::Some(#response))
+                }
+            },
+            _ => {
+                let handler_types = handlers.iter().map(|h| &h.type_name).collect::<Vec<_>>();
+                return Err(syn::Error::new(
+                    proc_macro2::Span::call_site(),
+                    format!("Optional message ({}) has multiple handlers {:?}", message_spec.name, handler_types)
+                ));
+            },
+        });
+    }
+
     Ok(match (message_spec.has_response, handlers) {
         (false, []) => {
             if message_spec.is_async {
@@ -61,25 +340,24 @@ fn make_handle_impl_body(message_spec: &MessageSpec, handlers: &[&Handler], unwr
                 quote!(())
             }
         },
-        (false, handlers) => {
-            let handler_types = handlers.iter().map(|h| &h.type_name);
-            let handler_exprs = handlers.iter().map(|h| get_member_expr(h));
+        (false, _) => {
             if message_spec.is_async {
+                let collect_responses = collect_responses();
                 quote!(
                     use ::futures::FutureExt;
-                    let message = message.clone();
+                    #collect_responses
+                    let responses = ::futures::future::join_all(responses);
                     async move {
-                        ::futures::future::join_all([#( < #handler_types as ::handler_structs::Handle::<#message_name> >::handle(&#handler_exprs, self, message.clone())),*]).await;
+                        responses.await;
                     }.boxed_local()
                 )
             } else {
-                quote!(#( < #handler_types as ::handler_structs::Handle::<#message_name> >::handle(&#handler_exprs, self, message.clone()); )*)
+                let calls = handler_statements(handlers, &message_name, unwrap_member, |call| quote!(#call;));
+                quote!(#(#calls)*)
             }
         },
         (true, [handler]) => {
-            let handler_type = &handler.type_name;
-            let handler_expr = get_member_expr(handler);
-            quote!( < #handler_type as ::handler_structs::Handle::<#message_name> >::handle(&#handler_expr, self, message))
+            handler_call(handler, &message_name, get_member_expr(handler), quote!(message))
         },
         (true, []) => {
             return Err(syn::Error::new(
@@ -97,40 +375,180 @@ fn make_handle_impl_body(message_spec: &MessageSpec, handlers: &[&Handler], unwr
     })
 }
 
-fn make_handle_impl_body_for_proxy(message_spec: &MessageSpec) -> TokenStream {
+// A message sent directly to the context always reaches the handlers, so try_handle can only time out.
+fn make_try_handle_impl_body(message_spec: &MessageSpec) -> TokenStream {
+    if message_spec.is_stream {
+        quote!(
+            let stream = ::futures::StreamExt::boxed_local(::futures::StreamExt::map(
+                ::context_structs::CtxHandle::handle(self, message),
+                ::std::result::Result::Ok,
+            ));
+            ::context_structs::timeout::stream_with_deadline(stream, ::context_structs::timeout::deadline(timeout))
+        )
+    } else if message_spec.is_async {
+        quote!(
+            use ::futures::FutureExt;
+            let deadline = ::context_structs::timeout::deadline(timeout);
+            ::context_structs::timeout::with_deadline(::context_structs::CtxHandle::handle(self, message), deadline).boxed_local()
+        )
+    } else {
+        // sync handlers can't be interrupted
+        quote!(
+            let _ = timeout;
+            ::std::result::Result::Ok(::context_structs::CtxHandle::handle(self, message))
+        )
+    }
+}
+
+// The partial context fails with NotInitialised if the one handler of a request hasn't been
+// initialised. Messages that can go to any number of handlers skip the missing ones.
+fn make_try_handle_impl_body_partial(message_spec: &MessageSpec, handlers: &[&Handler]) -> TokenStream {
+    let try_handle_body = make_try_handle_impl_body(message_spec);
+    let one_handler = message_spec.has_response && !message_spec.is_collect && !message_spec.is_first_match && !message_spec.is_optional;
+    let [handler] = handlers else {
+        return try_handle_body;
+    };
+    if !one_handler {
+        return try_handle_body;
+    }
+
+    let bare_expr = &handler.get_member_expr;
+    let error = quote!(::std::result::Result::Err(::context_structs::HandleError::NotInitialised));
+    let not_initialised = if message_spec.is_stream {
+        quote!(::futures::StreamExt::boxed_local(::futures::stream::once(async { #error })))
+    } else if message_spec.is_async {
+        quote!(::futures::FutureExt::boxed_local(async { #error }))
+    } else {
+        error
+    };
+    quote!(
+        if #bare_expr.is_none() {
+            let _ = (message, timeout);
+            return #not_initialised;
+        }
+        #try_handle_body
+    )
+}
+
+fn priority_tokens(priority: Priority) -> TokenStream {
+    match priority {
+        Priority::High => quote!(::message_structs::Priority::High),
+        Priority::Normal => quote!(::message_structs::Priority::Normal),
+        Priority::Low => quote!(::message_structs::Priority::Low),
+    }
+}
+
+fn make_try_handle_impl_body_for_proxy(message_spec: &MessageSpec) -> TokenStream {
     let enum_name = any_message_enum_name(message_spec);
+    let priority = priority_tokens(message_spec.priority);
+
+    // waiting for the response before the context runs, on its thread, would never end. Async
+    // requests are checked when they are first polled, so they can be made early and awaited later.
+    let check_running = quote!(
+        if !self.sender.can_wait_for_response() {
+            return ::std::result::Result::Err(::context_structs::HandleError::NotRunning);
+        }
+    );
+
+    // the deadline goes with the message so the context can cancel the handler when it passes
+    if message_spec.is_stream {
+        return quote!(
+            let deadline = ::context_structs::timeout::deadline(timeout);
+            let (sender, receiver) = ::context_structs::stream::channel();
+            let any_message = AnyMessage::#enum_name(message, sender, deadline.map(::context_structs::timeout::QueuedDeadline::At));
+            let stream = ::context_structs::stream::receive_items(async move {
+                #check_running
+                self.sender.get(#priority).send(any_message).await.map_err(|_| ::context_structs::HandleError::Closed)
+            }, receiver);
+            ::context_structs::timeout::stream_with_deadline(stream, deadline)
+        );
+    }
 
     let make_any_message = quote!(
+        let deadline = ::context_structs::timeout::deadline(timeout);
         let (sender, receiver) = ::oneshot::channel();
-        let any_message = AnyMessage::#enum_name(message, sender);
+        let any_message = AnyMessage::#enum_name(message, sender, deadline.map(::context_structs::timeout::QueuedDeadline::At));
     );
 
-    let send_snippet = if message_spec.is_async {
-        quote!(self.sender.send(any_message).await.unwrap();)
+    if message_spec.is_async {
+        quote!(
+            use ::futures::FutureExt;
+            #make_any_message
+            async move {
+                #check_running
+                let response = async {
+                    self.sender.get(#priority).send(any_message).await.map_err(|_| ::context_structs::HandleError::Closed)?;
+                    receiver.await.map_err(|_| ::context_structs::HandleError::Dropped)?
+                };
+                ::context_structs::timeout::with_deadline(response, deadline).await?
+            }.boxed()
+        )
     } else {
-        quote!(self.sender.try_send(any_message).unwrap();)
-    };
+        quote!(
+            #check_running
+            #make_any_message
+            self.sender.get(#priority).try_send(any_message).map_err(|e| match e {
+                ::smol::channel::TrySendError::Full(_) => ::context_structs::HandleError::Full,
+                ::smol::channel::TrySendError::Closed(_) => ::context_structs::HandleError::Closed,
+            })?;
+            match deadline {
+                ::std::option::Option::Some(deadline) => receiver.recv_deadline(deadline).map_err(|e| match e {
+                    ::oneshot::RecvTimeoutError::Timeout => ::context_structs::HandleError::TimedOut,
+                    ::oneshot::RecvTimeoutError::Disconnected => ::context_structs::HandleError::Dropped,
+                })?,
+                ::std::option::Option::None => receiver.recv().map_err(|_| ::context_structs::HandleError::Dropped)?,
+            }
+        )
+    }
+}
 
-    let return_response = if message_spec.is_async {
-        quote!(receiver.await.unwrap())
+// Queues the message straight away on sender and drops the receiver, so the response is thrown
+// away. The message's timeout still applies, from when the context takes it from the queue.
+fn make_post_impl_body(message_spec: &MessageSpec, sender: TokenStream) -> syn::Result<TokenStream> {
+    let message_name: TypePath = parse_str(message_spec.name)?;
+    let enum_name = any_message_enum_name(message_spec);
+    let priority = priority_tokens(message_spec.priority);
+    let channel = if message_spec.is_stream {
+        quote!(::context_structs::stream::channel())
     } else {
-        quote!(receiver.recv().unwrap())
+        quote!(::oneshot::channel())
     };
 
-    if message_spec.is_async {
+    Ok(quote!(
+        let deadline = ::context_structs::timeout::QueuedDeadline::posted(<#message_name as ::message_structs::Message>::get_message_spec().timeout);
+        let (sender, _) = #channel;
+        let any_message = AnyMessage::#enum_name(message, sender, deadline);
+        #sender.get(#priority).try_send(any_message).map_err(|e| match e {
+            ::smol::channel::TrySendError::Full(_) => ::context_structs::HandleError::Full,
+            ::smol::channel::TrySendError::Closed(_) => ::context_structs::HandleError::Closed,
+        })
+    ))
+}
+
+// handle on the proxy is try_handle without a timeout, panicking on errors. Like handle on the
+// context it ignores the message's #[pt_timeout(...)], which only applies through try_handle.
+fn make_handle_impl_body_for_proxy(message_spec: &MessageSpec) -> TokenStream {
+    let message_name = message_spec.name;
+    let panic_snippet = quote!(panic!("Failed to handle {} through the context proxy: {}", #message_name, e));
+
+    if message_spec.is_stream {
+        quote!(
+            ::futures::StreamExt::boxed_local(::futures::StreamExt::map(
+                ::context_structs::CtxTryHandle::try_handle_with_timeout(self, message, ::std::option::Option::None),
+                |response| response.unwrap_or_else(|e| #panic_snippet),
+            ))
+        )
+    } else if message_spec.is_async {
         quote!(
             use ::futures::FutureExt;
+            let response = ::context_structs::CtxTryHandle::try_handle_with_timeout(self, message, ::std::option::Option::None);
             async move {
-                #make_any_message
-                #send_snippet
-                #return_response
-            }.boxed()
+                response.await.unwrap_or_else(|e| #panic_snippet)
+            }.boxed_local()
         )
     } else {
         quote!(
-            #make_any_message
-            #send_snippet
-            #return_response
+            ::context_structs::CtxTryHandle::try_handle_with_timeout(self, message, ::std::option::Option::None).unwrap_or_else(|e| #panic_snippet)
         )
     }
 }
@@ -143,6 +561,14 @@ fn make_handle_impl(message_spec: &MessageSpec, handlers: &[Handler]) -> syn::Re
     let handle_body = make_handle_impl_body(message_spec, &handlers, false)?;
     let handle_body_with_unwrap = make_handle_impl_body(message_spec, &handlers, true)?;
     let handle_body_proxy = make_handle_impl_body_for_proxy(message_spec);
+    let try_handle_body = make_try_handle_impl_body(message_spec);
+    let try_handle_body_partial = make_try_handle_impl_body_partial(message_spec, &handlers);
+    let try_handle_body_proxy = make_try_handle_impl_body_for_proxy(message_spec);
+    // the context and the partial context post to their own queue, which is only read once the
+    // context runs
+    let post_body = make_post_impl_body(message_spec, quote!(self.context_proxy_sender))?;
+    let post_body_partial = make_post_impl_body(message_spec, quote!(self.context_proxy_sender.as_ref().unwrap()))?;
+    let post_body_proxy = make_post_impl_body(message_spec, quote!(self.sender))?;
 
     Ok(quote!(
         impl ::context_structs::CtxHandle<#message_name> for Context {
@@ -162,6 +588,42 @@ fn make_handle_impl(message_spec: &MessageSpec, handlers: &[Handler]) -> syn::Re
                 #handle_body_proxy
             }
         }
+
+        impl ::context_structs::CtxTryHandle<#message_name> for Context {
+            fn try_handle_with_timeout<'a>(&'a self, message: #message_name, timeout: ::std::option::Option<::std::time::Duration>) -> ::context_structs::TryResponse<'a, #message_name> {
+                #try_handle_body
+            }
+        }
+
+        impl ::context_structs::CtxTryHandle<#message_name> for PartialContext {
+            fn try_handle_with_timeout<'a>(&'a self, message: #message_name, timeout: ::std::option::Option<::std::time::Duration>) -> ::context_structs::TryResponse<'a, #message_name> {
+                #try_handle_body_partial
+            }
+        }
+
+        impl ::context_structs::CtxTryHandle<#message_name> for ContextProxy {
+            fn try_handle_with_timeout<'a>(&'a self, message: #message_name, timeout: ::std::option::Option<::std::time::Duration>) -> ::context_structs::TryResponse<'a, #message_name> {
+                #try_handle_body_proxy
+            }
+        }
+
+        impl ::context_structs::CtxPost<#message_name> for Context {
+            fn post(&self, message: #message_name) -> ::std::result::Result<(), ::context_structs::HandleError> {
+                #post_body
+            }
+        }
+
+        impl ::context_structs::CtxPost<#message_name> for PartialContext {
+            fn post(&self, message: #message_name) -> ::std::result::Result<(), ::context_structs::HandleError> {
+                #post_body_partial
+            }
+        }
+
+        impl ::context_structs::CtxPost<#message_name> for ContextProxy {
+            fn post(&self, message: #message_name) -> ::std::result::Result<(), ::context_structs::HandleError> {
+                #post_body_proxy
+            }
+        }
     ))
 }
 
@@ -173,50 +635,89 @@ fn make_context_config(handlers: &[Handler]) -> TokenStream {
         })
         .unzip();
 
+    // FromConfig is only implemented if every config is a ConfigSection. The bounds mention
+    // 'de, so they're checked where the config is loaded rather than here.
     quote!(
         pub struct ContextConfig {
             #(pub #handler_member_names_with_config: <#handler_types_with_config as ::handler_structs::Handler>::InitConfig),*
         }
+
+        impl<'de> ::context_structs::config::FromConfig<'de> for ContextConfig
+        where #(<#handler_types_with_config as ::handler_structs::Handler>::InitConfig: ::context_structs::config::ConfigSection<'de>),*
+        {
+            fn from_config(table: &'de ::context_structs::config::ConfigTable) -> ::std::result::Result<Self, ::context_structs::config::ConfigError> {
+                table.check_keys(&[#(stringify!(#handler_member_names_with_config)),*])?;
+                ::std::result::Result::Ok(Self {
+                    #(#handler_member_names_with_config: table.section(stringify!(#handler_member_names_with_config))?),*
+                })
+            }
+        }
     )
 }
 
 fn any_message_enum_name(spec: &MessageSpec) -> Ident {
-    let name = &spec.name.replace("_", "__").replace("::", "_");
-    let trimmed_name = name.trim_start_matches("_");
-    Ident::new(trimmed_name, Span::call_site())
+    // generic messages have the type arguments in their name, e.g. ::a::Echo<::std::string::String>,
+    // so anything that can't go in an identifier is spelled out.
+    let mut name = String::new();
+    for c in spec.name.replace("::", ":").chars() {
+        match c {
+            '_' => name.push_str("__"),
+            ':' => name.push('_'),
+            '<' => name.push_str("_Of_"),
+            ',' => name.push_str("_And_"),
+            '>' => name.push_str("_End"),
+            ' ' => (),
+            c if c.is_alphanumeric() => name.push(c),
+            c => name.push_str(&format!("_{:x}_", c as u32)),
+        }
+    }
+    Ident::new(name.trim_start_matches('_'), Span::call_site())
 }
 
 fn make_any_message_enum(message_specs: &[&'static MessageSpec]) -> TokenStream {
+    // the response is sent back as a Result so that a handler panicking can be reported to the sender
     let enum_types = message_specs.iter().map(|spec| {
         let message_type = syn::parse_str::<TypePath>(spec.name).unwrap();
-        if spec.has_response {
-            quote!(#message_type, ::oneshot::Sender<<#message_type as ::message_structs::Message>::UnwrappedResponse>)
+        if spec.is_stream {
+            quote!(#message_type, ::context_structs::stream::StreamSender<<#message_type as ::message_structs::Message>::UnwrappedResponse>, ::std::option::Option<::context_structs::timeout::QueuedDeadline>)
         } else {
-            quote!(#message_type, ::oneshot::Sender<()>)
+            quote!(#message_type, ::oneshot::Sender<::std::result::Result<<#message_type as ::message_structs::Message>::UnwrappedResponse, ::context_structs::HandleError>>, ::std::option::Option<::context_structs::timeout::QueuedDeadline>)
         }
     });
 
     let message_idents: Vec<_> = message_specs.iter().map(|s| any_message_enum_name(s)).collect();
 
     let match_arms = message_specs.iter().zip(&message_idents).map(|(spec, ident)| {
+        if spec.is_stream {
+            return quote!(Self::#ident(message, sender, deadline) => {
+                let deadline = ::context_structs::timeout::QueuedDeadline::dequeued(deadline);
+                ::context_structs::stream::send_items(|| ctx.handle(message), sender, deadline).await;
+            });
+        }
+
         let get_response_snippet = if spec.is_async {
-            quote!(ctx.handle(message).await)
+            quote!(
+                let response = ::futures::FutureExt::catch_unwind(::std::panic::AssertUnwindSafe(ctx.handle(message)));
+                let response = ::context_structs::timeout::with_deadline(response, deadline).await
+                    .and_then(|response| response.map_err(|_| ::context_structs::HandleError::HandlerPanicked));
+            )
         } else {
-            quote!(ctx.handle(message))
+            quote!(
+                let response = if ::context_structs::timeout::expired(deadline) {
+                    ::std::result::Result::Err(::context_structs::HandleError::TimedOut)
+                } else {
+                    ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| ctx.handle(message)))
+                        .map_err(|_| ::context_structs::HandleError::HandlerPanicked)
+                };
+            )
         };
 
-        if spec.has_response {
-            quote!(Self::#ident(message, sender) => {
-                let response = #get_response_snippet;
-                // ignore the error, it just means the receiver was dropped
-                let _ = sender.send(response);
-            })
-        } else {
-            quote!(Self::#ident(message, sender) => {
-                #get_response_snippet;
-                let _ = sender.send(()); // ignore the error, it just means the receiver was dropped
-            })
-        }
+        quote!(Self::#ident(message, sender, deadline) => {
+            let deadline = ::context_structs::timeout::QueuedDeadline::dequeued(deadline);
+            #get_response_snippet
+            // ignore the error, it just means the receiver was dropped
+            let _ = sender.send(response);
+        })
     });
 
     quote!(
@@ -234,7 +735,67 @@ fn make_any_message_enum(message_specs: &[&'static MessageSpec]) -> TokenStream
     )
 }
 
+fn make_serialized_impl(message_specs: &[&'static MessageSpec]) -> TokenStream {
+    let serde_specs = message_specs.iter().filter(|spec| spec.is_serde);
+    let match_arms = serde_specs.map(|spec| {
+        let message_type = syn::parse_str::<TypePath>(spec.name).unwrap();
+        let id = proc_macro2::Literal::u64_suffixed(spec.id);
+        let get_response_snippet = if spec.is_async {
+            quote!(ctx.handle(message).await)
+        } else {
+            quote!(ctx.handle(message))
+        };
+
+        quote!(#id => {
+            let message: #message_type = ::context_structs::serialized::decode_message(message)?;
+            let response = #get_response_snippet;
+            ::context_structs::serialized::encode_response::<F, #message_type>(&response)
+        })
+    });
+
+    quote!(
+        impl Context {
+            pub fn encode_message<F: ::context_structs::serialized::Format, M: ::message_structs::SerdeMessage>(message: &M) -> ::std::result::Result<::context_structs::serialized::SerializedMessage<F>, ::context_structs::serialized::SerializeError> where Self: ::context_structs::CtxHandle<M> {
+                ::context_structs::serialized::encode_message(message)
+            }
+
+            pub fn decode_message<F: ::context_structs::serialized::Format, M: ::message_structs::SerdeMessage>(message: &::context_structs::serialized::SerializedMessage<F>) -> ::std::result::Result<M, ::context_structs::serialized::SerializeError> where Self: ::context_structs::CtxHandle<M> {
+                ::context_structs::serialized::decode_message(message)
+            }
+
+            pub fn encode_response<F: ::context_structs::serialized::Format, M: ::message_structs::SerdeMessage>(response: &M::UnwrappedResponse) -> ::std::result::Result<::context_structs::serialized::SerializedResponse<F>, ::context_structs::serialized::SerializeError> where Self: ::context_structs::CtxHandle<M> {
+                ::context_structs::serialized::encode_response::<F, M>(response)
+            }
+
+            pub fn decode_response<F: ::context_structs::serialized::Format, M: ::message_structs::SerdeMessage>(response: &::context_structs::serialized::SerializedResponse<F>) -> ::std::result::Result<M::UnwrappedResponse, ::context_structs::serialized::SerializeError> where Self: ::context_structs::CtxHandle<M> {
+                ::context_structs::serialized::decode_response::<F, M>(response)
+            }
+
+            // Decodes the message, passes it to ctx and encodes the response. ctx can be the context or a proxy.
+            pub async fn handle_serialized<F: ::context_structs::serialized::Format>(ctx: &(impl ::message_list::C + ?Sized), message: &::context_structs::serialized::SerializedMessage<F>) -> ::std::result::Result<::context_structs::serialized::SerializedResponse<F>, ::context_structs::serialized::SerializeError> {
+                match message.id {
+                    #(#match_arms,)*
+                    id => ::std::result::Result::Err(::context_structs::serialized::SerializeError::UnknownMessage(id)),
+                }
+            }
+        }
+    )
+}
+
 pub fn context_impl(message_specs: Vec<&'static MessageSpec>, handler_specs: Vec<(&'static str, HandlerSpec)>) -> syn::Result<TokenStream> {
+    // ids are hashes of the message names so could in theory collide
+    {
+        let mut ids: HashMap<MessageId, &'static str> = HashMap::new();
+        for spec in message_specs.iter() {
+            if let Some(other) = ids.insert(spec.id, spec.name) {
+                return Err(syn::Error::new(
+                    Span::call_site(),
+                    format!("Messages {} and {} have the same id {}", other, spec.name, spec.id)
+                ));
+            }
+        }
+    }
+
     // make a vec of Handlers
     let handlers = handler_specs.iter()
         .map(|(name, spec)| Handler::from_handler_spec(spec, name))
@@ -242,7 +803,7 @@ pub fn context_impl(message_specs: Vec<&'static MessageSpec>, handler_specs: Vec
 
     let handle_impls = message_specs.iter()
         .map(|message_spec| {
-            make_handle_impl(*message_spec, &handlers)
+            make_handle_impl(message_spec, &handlers)
         })
         .reduce(|a: syn::Result<TokenStream>, b| {
             match (&a, &b) {
@@ -256,12 +817,27 @@ pub fn context_impl(message_specs: Vec<&'static MessageSpec>, handler_specs: Vec
     let context_config = make_context_config(&handlers);
 
     let handler_names = handlers.iter().map(|h| &h.member_name).collect::<Vec<_>>();
-    let handler_type_names = handlers.iter().map(|h| &h.type_name).collect::<Vec<_>>();
+    let handler_member_types = handlers.iter().map(|h| &h.member_type).collect::<Vec<_>>();
+
+    // a serial handler is locked while it handles a message, which a sync message can't wait for.
+    // A stream would have to keep it locked for as long as the stream is alive.
+    for handler in handlers.iter().filter(|h| h.spec.is_serial) {
+        if let Some(message) = handler.spec.handled_messages.iter().find(|m| !m.is_async || m.is_stream) {
+            return Err(syn::Error::new(
+                handler.spec.span,
+                format!("Serial handler {} can only handle async messages, not {}", handler.spec.name, message.name)
+            ));
+        }
+    }
 
-    // need to check that every init request required by each handler is provided by handlers
-    // which appear previously in the handler list.
+    // need to check that every init request required by each handler is provided by a handler
     {
-        let mut available_requests: HashSet<&'static str> = HashSet::new();
+        let available_requests: HashSet<MessageId> = handlers.iter()
+            .flat_map(|handler| &handler.spec.handled_messages)
+            .filter(|message| message.has_response) // only requests
+            .map(|request| request.id)
+            .collect();
+
         for handler in handlers.iter() {
             // check that all init messages are in fact requests
             for init_request in handler.spec.init_requests.iter() {
@@ -271,77 +847,184 @@ pub fn context_impl(message_specs: Vec<&'static MessageSpec>, handler_specs: Vec
                         format!("Handler {} requires init message {} which is not a request", handler.spec.name, init_request.name)
                     ));
                 }
+
+                // some requests can't be answered until the context is running, e.g. anything that
+                // waits on user input. The handler macros already reject these where the handler
+                // is defined, this catches handler specs made any other way. The span can only be
+                // context_type!, so the error names the handler's entry in define_context_type!.
+                if !init_request.available_during_init {
+                    return Err(syn::Error::new(
+                        handler.spec.span,
+                        format!("Handler {} ({}) requires init message {} which is marked #[pt_not_during_init]", handler.member_name, handler.spec.name, init_request.name)
+                    ));
+                }
             }
 
-            let unavailable_request = handler.spec.init_requests.iter().find(|req| !available_requests.contains(req.name));
+            let unavailable_request = handler.spec.init_requests.iter()
+                .find(|req| !can_have_no_handlers(req) && !available_requests.contains(&req.id));
             if let Some(r) = unavailable_request {
                 return Err(syn::Error::new(
                     handler.spec.span,
-                    format!("Handler {} requires request {} which is not provided by any previous handler", handler.spec.name, r.name)
+                    format!("Handler {} requires request {} which is not provided by any handler", handler.spec.name, r.name)
                 ));
             }
-            available_requests.extend(
-                handler.spec.handled_messages.iter()
-                    .filter(|message| message.has_response) // only requests
-                    .map(|request| &request.name)
-            );
         }
     }
 
-    let call_inits = handlers.iter().map(|handler| {
-        let handler_name = &handler.member_name;
-        let handler_type = &handler.type_name;
-        let init_ctx_snippet = if handler.spec.init_requests.is_empty() {
-            quote!(&())
-        } else {
+    let init_order = init_order(&handlers)?;
+    let init_waves = init_waves(&init_order);
+
+    // each wave is initialised concurrently, then put in the partial context for the next
+    let call_inits = init_waves.iter().map(|wave| {
+        let handler_names = wave.iter().map(|handler| &handler.member_name).collect::<Vec<_>>();
+        let init_ctx_names = handler_names.iter().map(|name| format_ident!("init_ctx_{}", name)).collect::<Vec<_>>();
+        let results = handler_names.iter().map(|name| format_ident!("handler_{}", name)).collect::<Vec<_>>();
+
+        let init_ctx_snippets = wave.iter().map(|handler| {
+            let handler_type = &handler.type_name;
             quote!({
                 type InitCtx<'a, Ctx> = <#handler_type as ::handler_structs::Handler>::InitCtx<'a, Ctx>;
                 &InitCtx{ctx: &partial_context}
             })
-        };
+        });
 
-        let config_snippet = if handler.spec.has_init_config {
-            quote!(config.#handler_name)
-        } else {
-            quote!(())
-        };
+        let inits = wave.iter().zip(&init_ctx_names).map(|(handler, init_ctx_name)| {
+            let handler_name = &handler.member_name;
+            let handler_type = &handler.type_name;
+            let config_snippet = if handler.spec.has_init_config {
+                quote!(config.#handler_name)
+            } else {
+                quote!(())
+            };
+            quote!(<#handler_type as ::handler_structs::HandlerInit>::init::<PartialContext>(#init_ctx_name, #config_snippet))
+        });
+
+        let store_results = wave.iter().zip(&results).map(|(handler, result)| {
+            let handler_name = &handler.member_name;
+            let handler_name_str = handler_name.to_string();
+            let handler_type_str = handler.spec.name;
+            let member = if handler.spec.is_serial {
+                quote!(::smol::lock::Mutex::new(handler))
+            } else {
+                quote!(handler)
+            };
+            quote!(
+                match ::handler_structs::IntoInitResult::into_init_result(#result) {
+                    ::std::result::Result::Ok(handler) => partial_context.#handler_name = ::std::option::Option::Some(#member),
+                    ::std::result::Result::Err(e) => error = error.or(::std::option::Option::Some(::context_structs::ContextInitError {
+                        handler: #handler_name_str,
+                        handler_type: #handler_type_str,
+                        error: e,
+                    })),
+                }
+            )
+        });
 
         quote!(
             {
-                let init_ctx = #init_ctx_snippet;
-                partial_context.#handler_name = ::std::option::Option::Some(<#handler_type as ::handler_structs::HandlerInit>::init::<PartialContext>(init_ctx, #config_snippet));
+                let (#(#results,)*) = {
+                    #( let #init_ctx_names = #init_ctx_snippets; )*
+                    ::futures::join!(#(#inits),*)
+                };
+                // the rest of the wave is kept so that it can be shut down too
+                let mut error = ::std::option::Option::None;
+                #(#store_results)*
+                if let ::std::option::Option::Some(error) = error {
+                    partial_context.shut_down_initialised().await;
+                    return ::std::result::Result::Err(error);
+                }
+            }
+        )
+    });
+
+    // in init order, once the context is complete
+    let call_links = init_waves.iter().flatten().filter(|h| h.spec.has_link).map(|handler| {
+        let handler_name = &handler.member_name;
+        let handler_type = &handler.type_name;
+        if handler.spec.is_serial {
+            let call = serial_call(handler, quote!(context.#handler_name), quote!(<#handler_type as ::handler_structs::HandlerLink>::link(&*handler, &context)));
+            quote!(#call;)
+        } else {
+            quote!(<#handler_type as ::handler_structs::HandlerLink>::link(&context.#handler_name, &context).await;)
+        }
+    });
+
+    // in reverse init order. Serial handlers finish the message they are handling first.
+    let shutdown_order = init_waves.iter().flatten().rev().filter(|h| h.spec.has_shutdown).collect::<Vec<_>>();
+    let call_shutdown = |handler: &Handler, member: TokenStream| {
+        let handler_type = &handler.type_name;
+        if handler.spec.is_serial {
+            let call = serial_call(handler, member, quote!(<#handler_type as ::handler_structs::HandlerShutdown>::shutdown(&*handler, self)));
+            quote!(#call;)
+        } else {
+            quote!(<#handler_type as ::handler_structs::HandlerShutdown>::shutdown(&#member, self).await;)
+        }
+    };
+    let call_shutdowns = shutdown_order.iter().map(|handler| {
+        let handler_name = &handler.member_name;
+        call_shutdown(handler, quote!(self.#handler_name))
+    }).collect::<Vec<_>>();
+    // when init fails, only the handlers that have been initialised
+    let call_partial_shutdowns = shutdown_order.iter().map(|handler| {
+        let handler_name = &handler.member_name;
+        let shutdown = call_shutdown(handler, quote!(handler));
+        quote!(
+            if let ::std::option::Option::Some(handler) = &self.#handler_name {
+                #shutdown
             }
         )
     });
 
     let any_message_enum = make_any_message_enum(&message_specs);
+    let serialized_impl = make_serialized_impl(&message_specs);
+
+    let message_catalog = schema::message_catalog(&message_specs, |message_spec| {
+        handlers.iter()
+            .filter(|h| h.handles(message_spec))
+            .map(|h| h.spec.name)
+            .collect()
+    }).to_string();
 
     Ok(quote!(
         #context_config
         #any_message_enum
+        #serialized_impl
 
         // partial context needs at least the sender so it can give out ContextProxy during init
         #[derive(Default)]
         struct PartialContext {
-            #( #handler_names: ::std::option::Option<#handler_type_names> ),*,
-            context_proxy_sender: ::std::option::Option<::smol::channel::Sender<AnyMessage>>,
-            context_proxy_receiver: ::std::option::Option<::smol::channel::Receiver<AnyMessage>>,
+            #( #handler_names: ::std::option::Option<#handler_member_types> ),*,
+            context_proxy_sender: ::std::option::Option<::context_structs::priority::PrioritySender<AnyMessage>>,
+            context_proxy_receiver: ::std::option::Option<::context_structs::priority::PriorityReceiver<AnyMessage>>,
+        }
+
+        impl PartialContext {
+            // Messages sent by the shutdown hooks can only go to handlers that were initialised
+            // before them.
+            async fn shut_down_initialised(&self) {
+                #(#call_partial_shutdowns)*
+            }
         }
 
         pub struct Context {
-            #( #handler_names: #handler_type_names ),*,
-            context_proxy_sender: ::smol::channel::Sender<AnyMessage>,
-            context_proxy_receiver: ::smol::channel::Receiver<AnyMessage>,
+            #( #handler_names: #handler_member_types ),*,
+            context_proxy_sender: ::context_structs::priority::PrioritySender<AnyMessage>,
+            context_proxy_receiver: ::context_structs::priority::PriorityReceiver<AnyMessage>,
         }
 
         #[derive(Clone)]
         pub struct ContextProxy {
-            sender: ::smol::channel::Sender<AnyMessage>,
+            sender: ::context_structs::priority::PrioritySender<AnyMessage>,
         }
 
         impl Context {
-            pub fn new(config: ContextConfig) -> Self {
-                let (context_proxy_sender, context_proxy_receiver) = ::smol::channel::bounded(1024);
+            // A JSON Schema describing every message in the context, for tooling and non-Rust clients.
+            pub fn message_catalog() -> &'static str {
+                #message_catalog
+            }
+
+            pub async fn new(config: ContextConfig) -> ::std::result::Result<Self, ::context_structs::ContextInitError> {
+                let (context_proxy_sender, context_proxy_receiver) = ::context_structs::priority::channel(1024);
                 let mut partial_context = PartialContext::default();
 
                 partial_context.context_proxy_sender = ::std::option::Option::Some(context_proxy_sender);
@@ -349,43 +1032,59 @@ pub fn context_impl(message_specs: Vec<&'static MessageSpec>, handler_specs: Vec
 
                 #(#call_inits)*
 
-                Self {
+                let context = Self {
                     #(#handler_names: partial_context.#handler_names.unwrap()),*,
                     context_proxy_sender: partial_context.context_proxy_sender.unwrap(),
                     context_proxy_receiver: partial_context.context_proxy_receiver.unwrap(),
-                }
+                };
+
+                #(#call_links)*
+
+                ::std::result::Result::Ok(context)
             }
 
             pub async fn run(&self) {
                 let executor = ::smol::LocalExecutor::new();
+                // what was sent during init is handled first, in order
+                self.context_proxy_receiver.start();
 
+                // Takes one message per task step, so a flood of messages doesn't hold up the tasks
+                // that are already running. The receiver hands out messages highest priority first.
                 loop {
-                    if executor.is_empty() {
-                        // if we have no tasks wait on the receiver
-                        let message = match self.context_proxy_receiver.recv().await {
-                            Ok(message) => message,
-                            Err(_) => {return;},
-                        };
-                        executor.spawn(async move {
-                            message.pass_to(self).await;
-                        }).detach();
-                    } else {
-                        // otherwise check for any new messages without waiting
-                        match self.context_proxy_receiver.try_recv() {
-                            Ok(message) => {
-                                executor.spawn(async move {
-                                    message.pass_to(self).await;
-                                }).detach();
-                            },
-                            Err(::smol::channel::TryRecvError::Empty) => {
-                                if !executor.is_empty() {
-                                    executor.tick().await;
+                    match self.context_proxy_receiver.try_recv() {
+                        Ok(message) => {
+                            executor.spawn(async move {
+                                message.pass_to(self).await;
+                            }).detach();
+                            executor.try_tick();
+                        },
+                        Err(::smol::channel::TryRecvError::Empty) => {
+                            if !executor.try_tick() {
+                                // nothing to do until a task wakes up or a message arrives
+                                let message = ::smol::future::or(
+                                    async {
+                                        executor.tick().await;
+                                        ::std::option::Option::None
+                                    },
+                                    self.context_proxy_receiver.recv(),
+                                ).await;
+
+                                if let ::std::option::Option::Some(message) = message {
+                                    executor.spawn(async move {
+                                        message.pass_to(self).await;
+                                    }).detach();
                                 }
-                            },
-                            Err(::smol::channel::TryRecvError::Closed) => {return;},
-                        }
+                            }
+                        },
+                        Err(::smol::channel::TryRecvError::Closed) => break,
                     }
                 }
+
+                // the handlers are shut down on the executor, so the messages that are still being
+                // handled carry on meanwhile. Whatever is left when they are done is dropped.
+                executor.run(async {
+                    #(#call_shutdowns)*
+                }).await;
             }
         }
 
@@ -426,3 +1125,125 @@ pub fn context_impl(message_specs: Vec<&'static MessageSpec>, handler_specs: Vec
         #handle_impls
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(name: &'static str, is_collect: bool) -> &'static MessageSpec {
+        Box::leak(Box::new(message_spec(name, is_collect)))
+    }
+
+    fn message_spec(name: &'static str, is_collect: bool) -> MessageSpec {
+        MessageSpec {
+            id: message_structs::message_id(name),
+            is_async: true,
+            is_stream: false,
+            is_collect,
+            is_first_match: false,
+            is_optional: false,
+            is_shared: false,
+            name,
+            has_response: true,
+            available_during_init: true,
+            is_serde: false,
+            priority: Priority::Normal,
+            timeout: None,
+            data: message_structs::MessageData::Struct(&[]),
+            response_type: Some("i32"),
+            docs: "",
+            attributes: &[],
+        }
+    }
+
+    fn handler_spec(name: &'static str, handled_messages: &[&'static MessageSpec], init_requests: &[&'static MessageSpec]) -> HandlerSpec {
+        HandlerSpec {
+            name,
+            handled_messages: handled_messages.to_vec(),
+            init_requests: init_requests.to_vec(),
+            has_init_config: false,
+            is_serial: false,
+            has_link: false,
+            has_shutdown: false,
+            span: Span::call_site(),
+        }
+    }
+
+    // the member names of the handlers in init order, or the cycle error
+    fn order(specs: &[(&str, HandlerSpec)]) -> Result<Vec<String>, String> {
+        let handlers = specs.iter().map(|(name, spec)| Handler::from_handler_spec(spec, name)).collect::<Vec<_>>();
+        init_order(&handlers)
+            .map(|order| order.iter().map(|handler| handler.member_name.to_string()).collect())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn init_requests_not_available_during_init_are_rejected() {
+        let x = Box::leak(Box::new(MessageSpec { available_during_init: false, ..message_spec("::m::X", false) }));
+        let specs = vec![
+            ("a", handler_spec("::h::A", &[x], &[])),
+            ("b", handler_spec("::h::B", &[], &[x])),
+        ];
+        let error = context_impl(vec![x], specs).unwrap_err();
+        assert_eq!(error.to_string(), "Handler b (::h::B) requires init message ::m::X which is marked #[pt_not_during_init]");
+    }
+
+    #[test]
+    fn handlers_without_dependencies_keep_their_order() {
+        let specs = [
+            ("a", handler_spec("::h::A", &[], &[])),
+            ("b", handler_spec("::h::B", &[], &[])),
+            ("c", handler_spec("::h::C", &[], &[])),
+        ];
+        assert_eq!(order(&specs).unwrap(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn handlers_come_after_their_dependencies_and_otherwise_in_order() {
+        let x = message("::m::X", false);
+        let specs = [
+            ("a", handler_spec("::h::A", &[], &[x])),
+            ("b", handler_spec("::h::B", &[], &[])),
+            ("c", handler_spec("::h::C", &[x], &[])),
+            ("d", handler_spec("::h::D", &[], &[])),
+        ];
+        assert_eq!(order(&specs).unwrap(), ["b", "c", "a", "d"]);
+    }
+
+    #[test]
+    fn collect_requests_wait_for_every_handler() {
+        let x = message("::m::X", true);
+        let specs = [
+            ("a", handler_spec("::h::A", &[], &[x])),
+            ("b", handler_spec("::h::B", &[x], &[])),
+            ("c", handler_spec("::h::C", &[], &[])),
+            ("d", handler_spec("::h::D", &[x], &[])),
+        ];
+        assert_eq!(order(&specs).unwrap(), ["b", "c", "d", "a"]);
+    }
+
+    #[test]
+    fn handler_needing_itself_is_a_cycle() {
+        let x = message("::m::X", false);
+        let specs = [
+            ("a", handler_spec("::h::A", &[], &[])),
+            ("b", handler_spec("::h::B", &[x], &[x])),
+        ];
+        assert_eq!(order(&specs).unwrap_err(), "Handlers depend on each other during init: b needs ::m::X from b");
+    }
+
+    #[test]
+    fn cycle_error_lists_only_the_cycle() {
+        let (x, y, z) = (message("::m::X", false), message("::m::Y", false), message("::m::Z", false));
+        // a needs the cycle between b and c but isn't part of it
+        let specs = [
+            ("a", handler_spec("::h::A", &[], &[x])),
+            ("b", handler_spec("::h::B", &[x, z], &[y])),
+            ("c", handler_spec("::h::C", &[y], &[z])),
+        ];
+        assert_eq!(
+            order(&specs).unwrap_err(),
+            "Handlers depend on each other during init: b needs ::m::Y from c, c needs ::m::Z from b"
+        );
+    }
+}
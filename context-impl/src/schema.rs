@@ -0,0 +1,209 @@
+use message_structs::{MessageSpec, FieldSpec, MessageData, VariantSpec};
+use serde_json::{json, Map, Value};
+use syn::{Type, GenericArgument, PathArguments};
+
+// Builds a JSON Schema (draft 2020-12) describing every message in the context. Each message is
+// a definition in $defs, keyed by its name. Information that JSON Schema has no keyword for
+// (ids, responses, which handlers handle the message, ...) is under "x-plantech".
+//
+// The catalog itself matches the messages that can be sent serialized, i.e. the #[pt_serde] ones,
+// as a SerializedMessage<Json>. The id tells them apart, so exactly one of them matches.
+pub fn message_catalog(message_specs: &[&'static MessageSpec], handled_by: impl Fn(&MessageSpec) -> Vec<&'static str>) -> Value {
+    let mut defs = Map::new();
+    for spec in message_specs {
+        defs.insert(spec.name.to_owned(), message_schema(spec, handled_by(spec)));
+    }
+
+    let serialized_messages: Vec<Value> = message_specs.iter()
+        .filter(|spec| spec.is_serde)
+        .map(|spec| json!({
+            "title": spec.name,
+            "type": "object",
+            "properties": {
+                "id": {"const": spec.id.to_string()},
+                "payload": {"$ref": format!("#/$defs/{}", spec.name.replace('~', "~0").replace('/', "~1"))},
+            },
+            "required": ["id", "payload"],
+        }))
+        .collect();
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Message catalog",
+        "oneOf": serialized_messages,
+        "$defs": defs,
+    })
+}
+
+fn message_schema(spec: &MessageSpec, handled_by: Vec<&'static str>) -> Value {
+    let mut schema = match &spec.data {
+        MessageData::Struct(fields) => fields_schema(fields),
+        MessageData::Enum(variants) => enum_schema(variants),
+    };
+    schema.insert("title".to_owned(), json!(spec.name));
+    if !spec.docs.is_empty() {
+        schema.insert("description".to_owned(), json!(spec.docs));
+    }
+
+    let response = match spec.response_type {
+        Some(ty) => json!({
+            "type": ty,
+            "schema": type_schema(ty),
+        }),
+        None => Value::Null,
+    };
+
+    schema.insert("x-plantech".to_owned(), json!({
+        // a string, like the ids of Json messages
+        "id": spec.id.to_string(),
+        "async": spec.is_async,
+        "stream": spec.is_stream,
+        "collect": spec.is_collect,
+        "first_match": spec.is_first_match,
+        "optional": spec.is_optional,
+        "shared": spec.is_shared,
+        "priority": spec.priority.name(),
+        "timeout_ms": spec.timeout.map(|timeout| timeout.as_millis() as u64),
+        "response": response,
+        "available_during_init": spec.available_during_init,
+        "serde": spec.is_serde,
+        "attributes": spec.attributes,
+        "handled_by": handled_by,
+    }));
+    Value::Object(schema)
+}
+
+// The schema of a struct, in the shape serde serializes it.
+fn fields_schema(fields: &[FieldSpec]) -> Map<String, Value> {
+    let mut schema = Map::new();
+    let is_tuple = fields.iter().any(|field| field.name.is_none());
+
+    if fields.is_empty() {
+        schema.insert("type".to_owned(), json!("object"));
+    } else if is_tuple && fields.len() == 1 {
+        // newtype structs serialize as their field
+        schema.extend(field_schema(&fields[0]));
+    } else if is_tuple {
+        schema.insert("type".to_owned(), json!("array"));
+        schema.insert("prefixItems".to_owned(), Value::Array(fields.iter().map(|f| Value::Object(field_schema(f))).collect()));
+        schema.insert("items".to_owned(), json!(false));
+    } else {
+        let properties: Map<String, Value> = fields.iter()
+            .map(|field| (field.name.unwrap().to_owned(), Value::Object(field_schema(field))))
+            .collect();
+        let required: Vec<&str> = fields.iter()
+            .filter(|field| !is_option(field.ty))
+            .map(|field| field.name.unwrap())
+            .collect();
+        schema.insert("type".to_owned(), json!("object"));
+        schema.insert("properties".to_owned(), Value::Object(properties));
+        schema.insert("required".to_owned(), json!(required));
+    }
+    schema
+}
+
+// serde's default, externally tagged, representation. Unit variants are just the variant name,
+// other variants are an object with the variant name as the only key.
+fn enum_schema(variants: &[VariantSpec]) -> Map<String, Value> {
+    let variant_schemas = variants.iter().map(|variant| {
+        let mut schema = if variant.fields.is_empty() {
+            let mut schema = Map::new();
+            schema.insert("const".to_owned(), json!(variant.name));
+            schema
+        } else {
+            let mut properties = Map::new();
+            properties.insert(variant.name.to_owned(), Value::Object(fields_schema(variant.fields)));
+            let mut schema = Map::new();
+            schema.insert("type".to_owned(), json!("object"));
+            schema.insert("properties".to_owned(), Value::Object(properties));
+            schema.insert("required".to_owned(), json!([variant.name]));
+            schema.insert("additionalProperties".to_owned(), json!(false));
+            schema
+        };
+        schema.insert("title".to_owned(), json!(variant.name));
+        if !variant.docs.is_empty() {
+            schema.insert("description".to_owned(), json!(variant.docs));
+        }
+        Value::Object(schema)
+    });
+
+    let mut schema = Map::new();
+    schema.insert("oneOf".to_owned(), Value::Array(variant_schemas.collect()));
+    schema
+}
+
+fn field_schema(field: &FieldSpec) -> Map<String, Value> {
+    let mut schema = match type_schema(field.ty) {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    };
+    if !field.docs.is_empty() {
+        schema.insert("description".to_owned(), json!(field.docs));
+    }
+    schema
+}
+
+fn is_option(ty: &str) -> bool {
+    matches!(syn::parse_str::<Type>(ty), Ok(Type::Path(p)) if p.path.segments.last().is_some_and(|s| s.ident == "Option"))
+}
+
+// Types are only known by name, so this covers the std types that serde has a fixed representation
+// for. Anything else is described by its rust type only.
+fn type_schema(ty: &str) -> Value {
+    let mut schema = match syn::parse_str::<Type>(ty) {
+        Ok(parsed) => parsed_type_schema(&parsed),
+        Err(_) => Map::new(),
+    };
+    schema.insert("x-rust-type".to_owned(), json!(ty));
+    Value::Object(schema)
+}
+
+fn parsed_type_schema(ty: &Type) -> Map<String, Value> {
+    let schema = match ty {
+        Type::Reference(r) => return parsed_type_schema(&r.elem),
+        Type::Paren(p) => return parsed_type_schema(&p.elem),
+        Type::Group(g) => return parsed_type_schema(&g.elem),
+        Type::Tuple(t) if t.elems.is_empty() => json!({"type": "null"}),
+        Type::Tuple(t) => json!({
+            "type": "array",
+            "prefixItems": t.elems.iter().map(|e| Value::Object(parsed_type_schema(e))).collect::<Vec<_>>(),
+            "items": false,
+        }),
+        Type::Array(a) => json!({"type": "array", "items": Value::Object(parsed_type_schema(&a.elem))}),
+        Type::Slice(s) => json!({"type": "array", "items": Value::Object(parsed_type_schema(&s.elem))}),
+        Type::Path(p) => {
+            let Some(last) = p.path.segments.last() else {
+                return Map::new();
+            };
+            let args: Vec<&Type> = match &last.arguments {
+                PathArguments::AngleBracketed(a) => a.args.iter()
+                    .filter_map(|arg| match arg {
+                        GenericArgument::Type(t) => Some(t),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+
+            match (last.ident.to_string().as_str(), args.as_slice()) {
+                ("i8" | "i16" | "i32" | "i64" | "i128" | "isize", []) => json!({"type": "integer"}),
+                ("u8" | "u16" | "u32" | "u64" | "u128" | "usize", []) => json!({"type": "integer", "minimum": 0}),
+                ("f32" | "f64", []) => json!({"type": "number"}),
+                ("bool", []) => json!({"type": "boolean"}),
+                ("String" | "str" | "char" | "PathBuf" | "Path", []) => json!({"type": "string"}),
+                ("Box" | "Rc" | "Arc" | "Cow", [inner]) => return parsed_type_schema(inner),
+                ("Option", [inner]) => json!({"anyOf": [Value::Object(parsed_type_schema(inner)), {"type": "null"}]}),
+                ("Vec" | "VecDeque" | "LinkedList", [inner]) => json!({"type": "array", "items": Value::Object(parsed_type_schema(inner))}),
+                ("HashSet" | "BTreeSet", [inner]) => json!({"type": "array", "items": Value::Object(parsed_type_schema(inner)), "uniqueItems": true}),
+                ("HashMap" | "BTreeMap", [_, value]) => json!({"type": "object", "additionalProperties": Value::Object(parsed_type_schema(value))}),
+                _ => return Map::new(),
+            }
+        },
+        _ => return Map::new(),
+    };
+
+    match schema {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
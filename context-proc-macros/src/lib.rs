@@ -1,6 +1,8 @@
+use std::collections::{HashMap, HashSet};
+
 use proc_macro2::{TokenStream, Ident};
 use quote::quote;
-use syn::{parse::Parse, Token};
+use syn::{parse::{Parse, ParseStream}, punctuated::Punctuated, Token, PathSegment};
 use proc_macro_helpers::{List, Dict};
 
 
@@ -9,6 +11,7 @@ mod kw {
 
     custom_keyword!(Messages);
     custom_keyword!(Handlers);
+    custom_keyword!(registered);
 }
 
 struct Messages {
@@ -61,7 +64,7 @@ impl Parse for DefineContextInput {
                 return Err(input.error("Expected either 'Messages' or 'Handlers'"));
             }
         }
-        Ok(Self { messages: messages, handlers: handlers })
+        Ok(Self { messages, handlers })
     }
 }
 
@@ -73,8 +76,7 @@ fn try_define_context_type(ts: TokenStream) -> syn::Result<TokenStream> {
 
     let insert_handler_snippets = input.handlers
         .iter()
-        .map(|handlers| &handlers.named_handlers.items)
-        .flatten()
+        .flat_map(|handlers| &handlers.named_handlers.items)
         .map(|pair| {
             let key = &pair.first;
             let value = &pair.second;
@@ -85,7 +87,7 @@ fn try_define_context_type(ts: TokenStream) -> syn::Result<TokenStream> {
 
     // check for duplicate names in the handlers
     let mut handler_names = ::std::collections::HashSet::new();
-    for pair in input.handlers.iter().map(|handlers| &handlers.named_handlers.items).flatten() {
+    for pair in input.handlers.iter().flat_map(|handlers| &handlers.named_handlers.items) {
         if pair.first == "context_proxy_sender" || pair.first == "context_proxy_receiver" {
             return Err(syn::Error::new_spanned(pair.first.clone(), "Handler name is reserved"));
         }
@@ -110,10 +112,137 @@ fn try_define_context_type(ts: TokenStream) -> syn::Result<TokenStream> {
     ))
 }
 
+// An entry in message_list!, either a message or every registered message in a module and its
+// submodules, e.g. example_messages::* or example_messages::editing::*
+enum MessageListItem {
+    Message(syn::TypePath),
+    Glob(syn::Path),
+}
+
+// The crate a glob is in, with the leading :: if it has one
+fn glob_crate(module: &syn::Path) -> syn::Path {
+    syn::Path {
+        leading_colon: module.leading_colon,
+        segments: module.segments.iter().take(1).cloned().collect(),
+    }
+}
+
+fn parse_glob(input: ParseStream) -> Option<syn::Path> {
+    let leading_colon: Option<Token![::]> = input.parse().ok()?;
+    let mut segments = Punctuated::new();
+    loop {
+        let ident: Ident = input.parse().ok()?;
+        segments.push_value(PathSegment::from(ident));
+        let colons: Token![::] = input.parse().ok()?;
+        if input.peek(Token![*]) {
+            let _: Token![*] = input.parse().ok()?;
+            return Some(syn::Path { leading_colon, segments });
+        }
+        segments.push_punct(colons);
+    }
+}
+
+impl Parse for MessageListItem {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let fork = input.fork();
+        if let Some(module) = parse_glob(&fork) {
+            use syn::parse::discouraged::Speculative;
+            input.advance_to(&fork);
+            return Ok(Self::Glob(module));
+        }
+        Ok(Self::Message(input.parse()?))
+    }
+}
+
+// The messages a crate's pt_registered_messages! passed back, relative to the crate root
+struct Registered {
+    _at: Token![@],
+    _kw: kw::registered,
+    krate: syn::Path,
+    messages: List<syn::Path>,
+}
+
+impl Parse for Registered {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        Ok(Self {
+            _at: input.parse()?,
+            _kw: input.parse()?,
+            krate: input.parse()?,
+            messages: input.parse()?,
+        })
+    }
+}
+
+struct MessageListInput {
+    items: List<MessageListItem>,
+    registered: Vec<Registered>,
+}
+
+impl Parse for MessageListInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let items = input.parse()?;
+        let mut registered = Vec::new();
+        while !input.is_empty() {
+            registered.push(input.parse()?);
+        }
+        Ok(Self { items, registered })
+    }
+}
+
 fn try_message_list(ts: TokenStream) -> syn::Result<TokenStream> {
-    let input: List<syn::TypePath> = syn::parse2(ts)?;
+    let input: MessageListInput = syn::parse2(ts.clone())?;
 
-    let message_paths: Vec<_> = input.values.iter().collect();
+    let registered: HashMap<String, &Registered> = input.registered.iter()
+        .map(|r| {
+            let krate = &r.krate;
+            (quote!(#krate).to_string(), r)
+        })
+        .collect();
+
+    // Globs are expanded by asking the crate for its registered messages. Its pt_registered_messages!
+    // calls message_list! again with the same input and the messages appended, until every glob's
+    // crate has been asked. It's called by name, so message_list has to be in scope wherever a glob
+    // is used, however it got there.
+    for item in input.items.values.iter() {
+        if let MessageListItem::Glob(module) = item {
+            let krate = glob_crate(module);
+            if !registered.contains_key(&quote!(#krate).to_string()) {
+                return Ok(quote!(
+                    #krate::pt_registered_messages!{ message_list { #ts @registered #krate } }
+                ));
+            }
+        }
+    }
+
+    let mut message_paths: Vec<syn::TypePath> = Vec::new();
+    let mut seen = HashSet::new();
+    for item in input.items.values.iter() {
+        let paths = match item {
+            MessageListItem::Message(path) => vec![path.clone()],
+            MessageListItem::Glob(module) => {
+                let krate = glob_crate(module);
+                let prefix: Vec<&Ident> = module.segments.iter().skip(1).map(|s| &s.ident).collect();
+                let paths: Vec<syn::TypePath> = registered[&quote!(#krate).to_string()].messages.values.iter()
+                    .filter(|path| {
+                        path.segments.len() > prefix.len()
+                            && path.segments.iter().zip(&prefix).all(|(segment, ident)| segment.ident == **ident)
+                    })
+                    .map(|path| syn::parse_quote!(#krate::#path))
+                    .collect();
+                if paths.is_empty() {
+                    return Err(syn::Error::new_spanned(module, "No messages are registered in this module"));
+                }
+                paths
+            },
+        };
+
+        // a message can be listed explicitly and also be matched by a glob
+        for path in paths {
+            if seen.insert(quote!(#path).to_string()) {
+                message_paths.push(path);
+            }
+        }
+    }
 
     Ok(quote!(
         pub fn messages() -> Vec<&'static ::message_structs::MessageSpec> {
@@ -121,7 +250,7 @@ fn try_message_list(ts: TokenStream) -> syn::Result<TokenStream> {
         }
 
 
-        pub trait C: #( ::context_structs::CtxHandle<#message_paths> + )* {
+        pub trait C: #( ::context_structs::CtxHandle<#message_paths> + ::context_structs::CtxTryHandle<#message_paths> + ::context_structs::CtxPost<#message_paths> + )* {
             // The normal context is not Send, but the proxy is. This is done using an mpsc
             // channel.
             fn proxy(&self) -> ::std::boxed::Box<dyn C + Send>;
@@ -0,0 +1,502 @@
+use std::{collections::{HashMap, HashSet}, fmt::Display, path::{Path, PathBuf}};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+// Builds a ContextConfig from layers of config files, environment variables and command line flags,
+// later layers overriding earlier ones. Every layer is a tree of keys, the top level keys are the
+// names of the handlers in the context, e.g. all of these set hello in the arithmetic handler's config
+//
+//   config.toml                 [arithmetic]
+//                               hello = true
+//   config.json                 {"arithmetic": {"hello": true}}
+//   environment variable        PT_ARITHMETIC__HELLO=true
+//   flag                        --arithmetic.hello=true
+//
+// Every handler's section is laid over the Default of its config, so only the fields that differ
+// have to be set. Keys that no config uses are an error, except that environment variables that
+// don't start with the name of a handler with a config are ignored, as they can be meant for
+// something else.
+//
+// The generated ContextConfig only implements FromConfig if every handler's config implements
+// Serialize, DeserializeOwned and Default, so loading is opt-in. Build it with
+//
+//   let table = ConfigLoader::new().file("config.toml").env("PT").args(std::env::args().skip(1)).load()?;
+//   let config: ContextConfig = table.get()?;
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLoader {
+    layers: Vec<Layer>,
+}
+
+#[derive(Debug, Clone)]
+enum Layer {
+    File { path: PathBuf, required: bool },
+    Env { prefix: String },
+    Args(Vec<String>),
+}
+
+impl ConfigLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A .toml or .json file, it's an error if it doesn't exist
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.layers.push(Layer::File { path: path.into(), required: true });
+        self
+    }
+
+    // A .toml or .json file, skipped if it doesn't exist
+    pub fn optional_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.layers.push(Layer::File { path: path.into(), required: false });
+        self
+    }
+
+    // The environment variables starting with prefix followed by _. The rest of the name is the key
+    // in upper case, with __ between the levels.
+    pub fn env(mut self, prefix: &str) -> Self {
+        self.layers.push(Layer::Env { prefix: format!("{}_", prefix) });
+        self
+    }
+
+    // Flags of the form --key.path=value, or --key.path on its own for true. Only flags with a . in
+    // the name are taken, so they can be mixed with the program's other arguments.
+    pub fn args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        self.layers.push(Layer::Args(args.into_iter().collect()));
+        self
+    }
+
+    pub fn load(&self) -> Result<ConfigTable, ConfigError> {
+        let mut table = ConfigTable::default();
+
+        for layer in &self.layers {
+            match layer {
+                Layer::File { path, required } => {
+                    let text = match std::fs::read_to_string(path) {
+                        Ok(text) => text,
+                        Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => continue,
+                        Err(e) => return Err(ConfigError::Io { path: path.clone(), error: e }),
+                    };
+                    let value = parse_file(path, &text)?;
+                    for key in value.as_object().unwrap().keys() {
+                        table.env_only.remove(key);
+                    }
+                    table.merge(&mut Vec::new(), value, &path.display().to_string());
+                },
+                Layer::Env { prefix } => {
+                    // sorted so the result doesn't depend on the order the environment is in
+                    let mut vars: Vec<_> = std::env::vars().filter(|(name, _)| name.starts_with(prefix.as_str())).collect();
+                    vars.sort();
+                    for (name, value) in vars {
+                        let key: Vec<String> = name[prefix.len()..].split("__").map(|part| part.to_lowercase()).collect();
+                        if key.iter().any(String::is_empty) {
+                            continue;
+                        }
+                        let is_new = !table.values.contains_key(&key[0]);
+                        table.set(&key, parse_value(&value), &name)?;
+                        table.raw.insert(key.join("."), value);
+                        if is_new {
+                            table.env_only.insert(key[0].clone());
+                        }
+                    }
+                },
+                Layer::Args(args) => {
+                    for arg in args {
+                        let Some(flag) = arg.strip_prefix("--") else { continue };
+                        let (name, value, raw) = match flag.split_once('=') {
+                            Some((name, value)) => (name, parse_value(value), Some(value)),
+                            None => (flag, Value::Bool(true), None),
+                        };
+                        if !name.contains('.') {
+                            continue;
+                        }
+                        let key: Vec<String> = name.split('.').map(str::to_owned).collect();
+                        table.set(&key, value, &format!("--{}", name))?;
+                        if let Some(raw) = raw {
+                            table.raw.insert(key.join("."), raw.to_owned());
+                        }
+                        table.env_only.remove(&key[0]);
+                    }
+                },
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+fn parse_file(path: &Path, text: &str) -> Result<Value, ConfigError> {
+    let parse_error = |error: String| ConfigError::Parse { path: path.to_path_buf(), error };
+
+    let value: Value = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str(text).map_err(|e| parse_error(e.to_string()))?,
+        Some("json") => serde_json::from_str(text).map_err(|e| parse_error(e.to_string()))?,
+        _ => return Err(parse_error("expected a .toml or .json file".to_string())),
+    };
+
+    if !value.is_object() {
+        return Err(parse_error("expected a table of handler names at the top level".to_string()));
+    }
+    Ok(value)
+}
+
+// Values from the environment and flags are json if they parse as json, so true, 3 and [1, 2] have
+// their types. Anything else is a string. The string as given is kept too, for fields that want a
+// string that happens to be valid json, e.g. a version of 1.0.
+fn parse_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()))
+}
+
+// The merged layers, along with where every key was last set so errors can point at it.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigTable {
+    values: Map<String, Value>,
+    origins: HashMap<String, String>,
+    // the values of environment variables and flags as they were given, by key
+    raw: HashMap<String, String>,
+    // the top level keys only set by environment variables
+    env_only: HashSet<String>,
+}
+
+impl ConfigTable {
+    pub fn get<'de, T: FromConfig<'de>>(&'de self) -> Result<T, ConfigError> {
+        T::from_config(self)
+    }
+
+    // The config for the handler called name, the section laid over the Default of the config. The
+    // values are copied out of the table, so the config can't borrow from it.
+    pub fn section<T: DeserializeOwned + Serialize + Default>(&self, name: &str) -> Result<T, ConfigError> {
+        let Some(section) = self.values.get(name) else {
+            return Ok(T::default());
+        };
+
+        let mut value = serde_json::to_value(T::default())
+            .map_err(|e| ConfigError::InvalidValue { key: name.to_owned(), origin: "defaults".to_owned(), error: e.to_string() })?;
+        merge_values(&mut value, section.clone());
+
+        loop {
+            let mut unused = Vec::new();
+            let mut track_unused = |path: serde_ignored::Path| unused.push(path.to_string());
+            let deserializer = serde_ignored::Deserializer::new(value.clone(), &mut track_unused);
+            let error = match serde_path_to_error::deserialize(deserializer) {
+                Ok(config) => {
+                    return match unused.first() {
+                        Some(path) => {
+                            let key = format!("{}.{}", name, path);
+                            Err(ConfigError::UnknownKey { origin: self.origin(&key), key })
+                        },
+                        None => Ok(config),
+                    };
+                },
+                Err(e) => e,
+            };
+
+            let path = error.path().to_string();
+            let key = if path == "." { name.to_owned() } else { format!("{}.{}", name, path) };
+            // try a value from an environment variable or flag as the string it was given as
+            if let Some(raw) = self.raw.get(&key) {
+                let parts: Vec<String> = key.split('.').skip(1).map(str::to_owned).collect();
+                let target = if parts.is_empty() { Some(&mut value) } else { lookup_mut(&mut value, &parts) };
+                if let Some(target) = target.filter(|target| !target.is_string()) {
+                    *target = Value::String(raw.clone());
+                    continue;
+                }
+            }
+            return Err(ConfigError::InvalidValue { origin: self.origin(&key), key, error: error.into_inner().to_string() });
+        }
+    }
+
+    // Errors on the first top level key that isn't one of names. Keys only set by environment
+    // variables are left out, the variables can be for something else with the same prefix.
+    pub fn check_keys(&self, names: &[&str]) -> Result<(), ConfigError> {
+        let unknown = self.values.keys()
+            .filter(|key| !self.env_only.contains(*key))
+            .find(|key| !names.contains(&key.as_str()));
+        match unknown {
+            Some(key) => Err(ConfigError::UnknownKey { origin: self.origin(key), key: key.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    // Where key or the closest key above it was last set
+    fn origin(&self, key: &str) -> String {
+        let mut key = key;
+        loop {
+            if let Some(origin) = self.origins.get(key) {
+                return origin.clone();
+            }
+            match key.rfind(['.', '[']) {
+                Some(i) => key = &key[..i],
+                None => return "defaults".to_owned(),
+            }
+        }
+    }
+
+    fn set(&mut self, key: &[String], value: Value, origin: &str) -> Result<(), ConfigError> {
+        if key.iter().any(String::is_empty) {
+            return Err(ConfigError::InvalidKey(origin.to_owned()));
+        }
+        let value = key.iter().rev().fold(value, |value, part| Value::Object(Map::from_iter([(part.clone(), value)])));
+        self.merge(&mut Vec::new(), value, origin);
+        Ok(())
+    }
+
+    // Merges value into the table at key, tables are merged key by key and anything else replaces
+    // what was there
+    fn merge(&mut self, key: &mut Vec<String>, value: Value, origin: &str) {
+        let Value::Object(map) = value else {
+            self.origins.insert(key.join("."), origin.to_owned());
+            self.raw.remove(&key.join("."));
+            insert(&mut self.values, key, value);
+            return;
+        };
+
+        if !key.is_empty() {
+            self.origins.insert(key.join("."), origin.to_owned());
+            // a table replaces a value that isn't a table
+            if !matches!(lookup(&self.values, key), Some(Value::Object(_))) {
+                insert(&mut self.values, key, Value::Object(Map::new()));
+            }
+        }
+        for (part, value) in map {
+            key.push(part);
+            self.merge(key, value, origin);
+            key.pop();
+        }
+    }
+}
+
+// Lays overlay over value, tables are merged key by key and anything else replaces what was there
+fn merge_values(value: &mut Value, overlay: Value) {
+    match (value, overlay) {
+        (Value::Object(values), Value::Object(overlay)) => {
+            for (key, overlay) in overlay {
+                match values.get_mut(&key) {
+                    Some(value) => merge_values(value, overlay),
+                    None => {
+                        values.insert(key, overlay);
+                    },
+                }
+            }
+        },
+        (value, overlay) => *value = overlay,
+    }
+}
+
+fn lookup_mut<'a>(value: &'a mut Value, key: &[String]) -> Option<&'a mut Value> {
+    key.iter().try_fold(value, |value, part| value.as_object_mut()?.get_mut(part))
+}
+
+fn lookup<'a>(values: &'a Map<String, Value>, key: &[String]) -> Option<&'a Value> {
+    let (last, parents) = key.split_last()?;
+    let mut values = values;
+    for part in parents {
+        values = values.get(part)?.as_object()?;
+    }
+    values.get(last)
+}
+
+// The parents of key are already tables, merge makes them on the way down. key isn't empty, only
+// tables are merged at the top level.
+fn insert(values: &mut Map<String, Value>, key: &[String], value: Value) {
+    let (last, parents) = key.split_last().unwrap();
+    let mut values = values;
+    for part in parents {
+        values = values.get_mut(part).and_then(Value::as_object_mut).unwrap();
+    }
+    values.insert(last.clone(), value);
+}
+
+// Implemented by the generated ContextConfig when every handler's config can be loaded.
+pub trait FromConfig<'de>: Sized {
+    fn from_config(table: &'de ConfigTable) -> Result<Self, ConfigError>;
+}
+
+// A handler config that can be loaded, i.e. one that implements Serialize, DeserializeOwned and
+// Default. 'de isn't used, it's only there so that the bounds on the generated FromConfig impl
+// mention a generic and are checked where the config is loaded.
+pub trait ConfigSection<'de>: DeserializeOwned + Serialize + Default {}
+
+impl<'de, T: DeserializeOwned + Serialize + Default> ConfigSection<'de> for T {}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // A config file couldn't be read
+    Io { path: PathBuf, error: std::io::Error },
+    // A config file isn't valid toml or json
+    Parse { path: PathBuf, error: String },
+    // An environment variable or flag with an empty part in its key, e.g. --arithmetic..hello
+    InvalidKey(String),
+    // A key that isn't the name of a handler with a config, or that its config doesn't use
+    UnknownKey { key: String, origin: String },
+    // The value at key doesn't fit the handler's config
+    InvalidValue { key: String, origin: String, error: String },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "couldn't read {}: {}", path.display(), error),
+            Self::Parse { path, error } => write!(f, "couldn't parse {}: {}", path.display(), error),
+            Self::InvalidKey(origin) => write!(f, "invalid config key in {}", origin),
+            Self::UnknownKey { key, origin } => write!(f, "unknown config key {} (from {})", key, origin),
+            Self::InvalidValue { key, origin, error } => write!(f, "invalid config value for {} (from {}): {}", key, origin, error),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+    struct Example {
+        name: String,
+        count: u32,
+        nested: Nested,
+    }
+
+    #[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+    struct Nested {
+        enabled: bool,
+        level: u32,
+    }
+
+    // a layer as a file called origin would give it
+    fn merge(table: &mut ConfigTable, origin: &str, value: Value) {
+        table.merge(&mut Vec::new(), value, origin);
+    }
+
+    fn args(args: &[&str]) -> Result<ConfigTable, ConfigError> {
+        ConfigLoader::new().args(args.iter().map(|arg| arg.to_string())).load()
+    }
+
+    // the error from loading the example section from args
+    fn section_error(args: &[&str]) -> ConfigError {
+        self::args(args).unwrap().section::<Example>("example").unwrap_err()
+    }
+
+    #[test]
+    fn later_layers_override_key_by_key() {
+        let mut table = ConfigTable::default();
+        merge(&mut table, "a.toml", serde_json::json!({"example": {"count": 1, "nested": {"enabled": true, "level": 2}}}));
+        merge(&mut table, "b.json", serde_json::json!({"example": {"nested": {"level": 3}}}));
+        table.set(&["example".to_owned(), "count".to_owned()], Value::from(5), "--example.count").unwrap();
+
+        let example: Example = table.section("example").unwrap();
+        assert_eq!(example, Example { name: String::new(), count: 5, nested: Nested { enabled: true, level: 3 } });
+    }
+
+    #[test]
+    fn tables_and_values_replace_each_other() {
+        let mut table = ConfigTable::default();
+        merge(&mut table, "a.toml", serde_json::json!({"example": {"nested": 1}}));
+        merge(&mut table, "b.toml", serde_json::json!({"example": {"nested": {"level": 2}}}));
+        assert_eq!(table.values["example"], serde_json::json!({"nested": {"level": 2}}));
+
+        merge(&mut table, "c.toml", serde_json::json!({"example": {"nested": 3}}));
+        assert_eq!(table.values["example"], serde_json::json!({"nested": 3}));
+    }
+
+    #[test]
+    fn origin_falls_back_to_the_closest_key_set() {
+        let mut table = ConfigTable::default();
+        merge(&mut table, "a.toml", serde_json::json!({"example": {"count": 1, "nested": {"enabled": true, "level": 2}}}));
+        merge(&mut table, "b.toml", serde_json::json!({"example": {"nested": {"level": 3}}}));
+
+        assert_eq!(table.origin("example.nested.level"), "b.toml");
+        assert_eq!(table.origin("example.nested.enabled"), "a.toml");
+        assert_eq!(table.origin("example.count"), "a.toml");
+        assert_eq!(table.origin("example.nested.level.deeper"), "b.toml");
+        assert_eq!(table.origin("example.nested[0]"), "b.toml");
+        assert_eq!(table.origin("other.count"), "defaults");
+    }
+
+    #[test]
+    fn empty_key_part_is_an_invalid_key() {
+        let error = args(&["--example..count=1"]).unwrap_err();
+        assert!(matches!(&error, ConfigError::InvalidKey(origin) if origin == "--example..count"), "{}", error);
+
+        let error = ConfigTable::default().set(&["example".to_owned(), String::new()], Value::from(1), "origin").unwrap_err();
+        assert!(matches!(&error, ConfigError::InvalidKey(origin) if origin == "origin"), "{}", error);
+    }
+
+    #[test]
+    fn args_without_a_dot_are_left_for_the_program() {
+        let table = args(&["positional", "--verbose", "--example.nested.enabled", "--example.count=2"]).unwrap();
+        table.check_keys(&["example"]).unwrap();
+        let example: Example = table.section("example").unwrap();
+        assert_eq!(example, Example { name: String::new(), count: 2, nested: Nested { enabled: true, level: 0 } });
+    }
+
+    #[test]
+    fn sections_are_laid_over_defaults() {
+        let table = ConfigTable::default();
+        assert_eq!(table.section::<Example>("example").unwrap(), Example::default());
+
+        let table = args(&["--example.nested.level=4"]).unwrap();
+        let example: Example = table.section("example").unwrap();
+        assert_eq!(example, Example { name: String::new(), count: 0, nested: Nested { enabled: false, level: 4 } });
+    }
+
+    #[test]
+    fn values_that_look_like_json_can_be_strings() {
+        let table = args(&["--example.name=123", "--example.count=7"]).unwrap();
+        let example: Example = table.section("example").unwrap();
+        assert_eq!(example.name, "123");
+        assert_eq!(example.count, 7);
+    }
+
+    #[test]
+    fn invalid_values_name_their_key_and_origin() {
+        let error = section_error(&["--example.count=many"]);
+        assert!(matches!(&error, ConfigError::InvalidValue { key, origin, .. } if key == "example.count" && origin == "--example.count"), "{}", error);
+
+        let mut table = ConfigTable::default();
+        merge(&mut table, "config.toml", serde_json::json!({"example": {"nested": {"level": "high"}}}));
+        let error = table.section::<Example>("example").unwrap_err();
+        assert!(matches!(&error, ConfigError::InvalidValue { key, origin, .. } if key == "example.nested.level" && origin == "config.toml"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_keys_name_their_key_and_origin() {
+        let error = section_error(&["--example.nested.colour=red"]);
+        assert!(matches!(&error, ConfigError::UnknownKey { key, origin } if key == "example.nested.colour" && origin == "--example.nested.colour"), "{}", error);
+
+        let mut table = ConfigTable::default();
+        merge(&mut table, "config.toml", serde_json::json!({"example": {}, "other": {"count": 1}}));
+        let error = table.check_keys(&["example"]).unwrap_err();
+        assert!(matches!(&error, ConfigError::UnknownKey { key, origin } if key == "other" && origin == "config.toml"), "{}", error);
+    }
+
+    #[test]
+    fn unrelated_environment_variables_are_ignored() {
+        // a prefix of its own, as the tests share the environment
+        std::env::set_var("PTCONFIGTEST_EXAMPLE__COUNT", "3");
+        std::env::set_var("PTCONFIGTEST_EXAMPLE__NAME", "4");
+        std::env::set_var("PTCONFIGTEST_UNRELATED", "value");
+        std::env::set_var("PTCONFIGTEST__LEADING", "value");
+
+        let loader = ConfigLoader::new().env("PTCONFIGTEST");
+        let table = loader.load().unwrap();
+        table.check_keys(&["example"]).unwrap();
+        let example: Example = table.section("example").unwrap();
+        assert_eq!((example.name.as_str(), example.count), ("4", 3));
+
+        // unless something else sets the key too
+        let table = loader.args(["--unrelated.x=1".to_owned()]).load().unwrap();
+        let error = table.check_keys(&["example"]).unwrap_err();
+        assert!(matches!(&error, ConfigError::UnknownKey { key, origin } if key == "unrelated" && origin == "--unrelated.x"), "{}", error);
+    }
+}
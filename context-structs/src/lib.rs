@@ -1,5 +1,129 @@
+use std::{fmt::Display, time::Duration};
+
 use message_structs::Message;
 
+pub mod config;
+pub mod priority;
+pub mod serial;
+pub mod serialized;
+pub mod stream;
+pub mod timeout;
+
+// Waits as long as the handlers take, the message's #[pt_timeout(...)] only applies to try_handle.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot handle the message `{T}`",
+    label = "`{T}` cannot be sent through this context",
+    note = "a message has to be listed in message_list! to be sent through a context, and in #[pt_init(...)] to be sent during handler init"
+)]
 pub trait CtxHandle<T: Message> {
     fn handle<'a>(&'a self, message: T) -> T::Response<'a>;
 }
+
+// The result of try_handle, i.e. Result<UnwrappedResponse, HandleError> wrapped in a future if the
+// message is async.
+pub type TryResponse<'a, T> = <T as Message>::Wrapped<'a, Result<<T as Message>::UnwrappedResponse, HandleError>>;
+
+// Like CtxHandle, but reports failures instead of panicking. A message sent directly to the context
+// always reaches the handlers, so the context can only fail with TimedOut. Sync messages handled
+// directly by the context can't be interrupted, so they ignore the timeout.
+pub trait CtxTryHandle<T: Message> {
+    // No timeout if timeout is None
+    fn try_handle_with_timeout<'a>(&'a self, message: T, timeout: Option<Duration>) -> TryResponse<'a, T>;
+
+    // Times out after the message's #[pt_timeout(...)], if it has one.
+    fn try_handle<'a>(&'a self, message: T) -> TryResponse<'a, T> {
+        self.try_handle_with_timeout(message, T::get_message_spec().timeout)
+    }
+
+    fn handle_with_timeout<'a>(&'a self, message: T, timeout: Duration) -> TryResponse<'a, T> {
+        self.try_handle_with_timeout(message, Some(timeout))
+    }
+}
+
+// Sends a message without waiting for it to be handled, and drops the response. Unlike a future
+// from handle that is never polled, the message is queued straight away. It's handled once the
+// context gets to it, so messages posted during init wait until the context runs.
+pub trait CtxPost<T: Message> {
+    fn post(&self, message: T) -> Result<(), HandleError>;
+}
+
+// Why try_handle or post failed, through a proxy or on the context itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleError {
+    // The context has quit
+    Closed,
+    // The context's queue is full. Only sync messages and post can get this, async messages wait
+    // for space.
+    Full,
+    // The context stopped without answering the message
+    Dropped,
+    // A handler panicked while handling the message
+    HandlerPanicked,
+    // The message wasn't handled before its deadline, the handler was cancelled if it had started
+    TimedOut,
+    // A handler failed to initialise before the handler of the message was, so the shutdown hooks
+    // can't ask it anything
+    NotInitialised,
+    // A request through a proxy on the thread that runs the context, before it runs. The response
+    // would never come, as the context only gets to the message once this thread stops waiting.
+    NotRunning,
+}
+
+impl Display for HandleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed => write!(f, "the context is closed"),
+            Self::Full => write!(f, "the context's queue is full"),
+            Self::Dropped => write!(f, "the context stopped before answering the message"),
+            Self::HandlerPanicked => write!(f, "a handler panicked while handling the message"),
+            Self::TimedOut => write!(f, "the message timed out"),
+            Self::NotInitialised => write!(f, "the handler of the message wasn't initialised"),
+            Self::NotRunning => write!(f, "the context isn't running yet, and this is the thread that runs it"),
+        }
+    }
+}
+
+impl std::error::Error for HandleError {}
+
+// The name HandleError had when only proxies could fail
+pub type ProxyError = HandleError;
+
+// A handler failed to initialise, so the context wasn't created. The handlers that were already
+// initialised have been shut down.
+#[derive(Debug)]
+pub struct ContextInitError {
+    // The name of the handler in define_context_type!
+    pub handler: &'static str,
+    pub handler_type: &'static str,
+    pub error: Box<dyn std::error::Error>,
+}
+
+impl Display for ContextInitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "handler {} ({}) failed to initialise: {}", self.handler, self.handler_type, self.error)
+    }
+}
+
+impl std::error::Error for ContextInitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+// Compile time check that Ctx can handle M, e.g.
+// const _: () = assert_can_handle::<Context, Add1>();
+pub const fn assert_can_handle<Ctx: CtxHandle<M> + ?Sized, M: Message>() {}
+
+pub fn call<'a, Ctx: CtxHandle<M> + ?Sized, M: Message>(ctx: &'a Ctx, message: M) -> M::Response<'a> {
+    ctx.handle(message)
+}
+
+// call!(ctx, message) is the same as ctx.handle(message), but if ctx cannot handle the message the
+// error points at the call rather than at a missing CtxHandle bound on the context. ctx can be a
+// reference to a context or a Box<dyn C>.
+#[macro_export]
+macro_rules! call {
+    ($ctx:expr, $message:expr) => {
+        $crate::call(&*$ctx, $message)
+    };
+}
@@ -0,0 +1,206 @@
+use std::{cell::Cell, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread::{self, ThreadId}};
+
+use message_structs::Priority;
+use smol::channel::{Sender, Receiver, TryRecvError};
+
+// The queues between the proxies and the context, one per priority. Each queue holds at most
+// capacity messages.
+//
+// Until the receiver is started, when the context starts running, every message goes in one more
+// queue regardless of its priority. That queue is emptied first, so the messages sent during init
+// are handled in the order they were sent.
+//
+// The receiver is made on the thread that runs the context, so the senders know which thread can't
+// wait for a response before the start.
+pub fn channel<T>(capacity: usize) -> (PrioritySender<T>, PriorityReceiver<T>) {
+    let (high_sender, high_receiver) = smol::channel::bounded(capacity);
+    let (normal_sender, normal_receiver) = smol::channel::bounded(capacity);
+    let (low_sender, low_receiver) = smol::channel::bounded(capacity);
+    let (before_start_sender, before_start_receiver) = smol::channel::bounded(capacity);
+    let started = Arc::new(AtomicBool::new(false));
+
+    (
+        PrioritySender {
+            senders: [high_sender, normal_sender, low_sender],
+            before_start: before_start_sender,
+            started: started.clone(),
+            receiver_thread: thread::current().id(),
+        },
+        PriorityReceiver {
+            receivers: [high_receiver, normal_receiver, low_receiver],
+            before_start: before_start_receiver,
+            started,
+            passed_over: Cell::new([0; 3]),
+        },
+    )
+}
+
+pub struct PrioritySender<T> {
+    senders: [Sender<T>; 3],
+    before_start: Sender<T>,
+    started: Arc<AtomicBool>,
+    receiver_thread: ThreadId,
+}
+
+// derive would require T: Clone
+impl<T> Clone for PrioritySender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            senders: self.senders.clone(),
+            before_start: self.before_start.clone(),
+            started: self.started.clone(),
+            receiver_thread: self.receiver_thread,
+        }
+    }
+}
+
+impl<T> PrioritySender<T> {
+    pub fn get(&self, priority: Priority) -> &Sender<T> {
+        if self.started.load(Ordering::Acquire) {
+            &self.senders[priority as usize]
+        } else {
+            &self.before_start
+        }
+    }
+
+    // Before the start, the thread that runs the context would wait forever for the response to
+    // anything it sends, as nothing takes messages off the queues until it gets to run. Other
+    // threads can wait, they get the response once the context runs.
+    pub fn can_wait_for_response(&self) -> bool {
+        self.started.load(Ordering::Acquire) || thread::current().id() != self.receiver_thread
+    }
+
+    pub fn close(&self) {
+        for sender in self.senders.iter().chain([&self.before_start]) {
+            sender.close();
+        }
+    }
+}
+
+// A lower priority queue is passed over when a message is taken from a higher priority queue while
+// it has messages waiting. After this many times in a row it gets the next turn.
+const STARVATION_LIMIT: usize = 8;
+
+pub struct PriorityReceiver<T> {
+    receivers: [Receiver<T>; 3],
+    before_start: Receiver<T>,
+    started: Arc<AtomicBool>,
+    passed_over: Cell<[usize; 3]>,
+}
+
+impl<T> PriorityReceiver<T> {
+    // From now on messages go in the queue for their priority
+    pub fn start(&self) {
+        self.started.store(true, Ordering::Release);
+    }
+
+    // Takes the message sent before the start that is waiting longest, or else the highest
+    // priority message that is waiting. Closed once every queue is closed and empty.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        // a sender can still be putting a message in before_start just after the start
+        let before_start = self.before_start.try_recv();
+        if let Ok(message) = before_start {
+            return Ok(message);
+        }
+
+        let mut passed_over = self.passed_over.get();
+        let starved = (0..3).find(|&i| passed_over[i] >= STARVATION_LIMIT);
+
+        let mut all_closed = true;
+        for i in starved.into_iter().chain(0..3) {
+            match self.receivers[i].try_recv() {
+                Ok(message) => {
+                    passed_over[i] = 0;
+                    for (lower, count) in passed_over.iter_mut().enumerate().skip(i + 1) {
+                        if !self.receivers[lower].is_empty() {
+                            *count += 1;
+                        }
+                    }
+                    self.passed_over.set(passed_over);
+                    return Ok(message);
+                },
+                Err(TryRecvError::Empty) => {
+                    passed_over[i] = 0;
+                    all_closed = false;
+                },
+                Err(TryRecvError::Closed) => (),
+            }
+        }
+
+        self.passed_over.set(passed_over);
+        if all_closed && matches!(before_start, Err(TryRecvError::Closed)) {
+            Err(TryRecvError::Closed)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    // Waits for a message on any queue. None if a queue is closed, try_recv then tells whether
+    // they all are.
+    pub async fn recv(&self) -> Option<T> {
+        let [high, normal, low] = &self.receivers;
+        smol::future::or(
+            async { self.before_start.recv().await.ok() },
+            smol::future::or(
+                async { high.recv().await.ok() },
+                smol::future::or(
+                    async { normal.recv().await.ok() },
+                    async { low.recv().await.ok() },
+                ),
+            ),
+        ).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_all(sender: &PrioritySender<&'static str>, messages: &[(Priority, &'static str)]) {
+        for (priority, message) in messages {
+            sender.get(*priority).try_send(message).unwrap();
+        }
+    }
+
+    fn receive_all(receiver: &PriorityReceiver<&'static str>) -> Vec<&'static str> {
+        std::iter::from_fn(|| receiver.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn highest_priority_first_once_started() {
+        let (sender, receiver) = channel(16);
+        receiver.start();
+        send_all(&sender, &[(Priority::Low, "low"), (Priority::Normal, "normal"), (Priority::High, "high")]);
+        assert_eq!(receive_all(&receiver), ["high", "normal", "low"]);
+    }
+
+    #[test]
+    fn sent_before_start_in_order_and_first() {
+        let (sender, receiver) = channel(16);
+        send_all(&sender, &[(Priority::Low, "low"), (Priority::High, "high")]);
+        receiver.start();
+        send_all(&sender, &[(Priority::High, "high after start")]);
+        assert_eq!(receive_all(&receiver), ["low", "high", "high after start"]);
+    }
+
+    #[test]
+    fn closed_once_every_queue_is_empty() {
+        let (sender, receiver) = channel(16);
+        send_all(&sender, &[(Priority::Normal, "before start")]);
+        receiver.start();
+        send_all(&sender, &[(Priority::Normal, "after start")]);
+        sender.close();
+        assert_eq!(receive_all(&receiver), ["before start", "after start"]);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Closed));
+    }
+
+    #[test]
+    fn lower_priorities_are_not_starved() {
+        let (sender, receiver) = channel(16);
+        receiver.start();
+        send_all(&sender, &[(Priority::Low, "low")]);
+        send_all(&sender, &[(Priority::High, "high"); STARVATION_LIMIT + 1]);
+        let received = receive_all(&receiver);
+        assert_eq!(received.iter().position(|message| *message == "low"), Some(STARVATION_LIMIT));
+    }
+}
@@ -0,0 +1,58 @@
+use std::{cell::RefCell, future::Future, pin::Pin, task::{Context, Poll}};
+
+use smol::lock::{Mutex, MutexGuard};
+
+// A serial handler is locked for the whole of a call, including while it waits for the responses
+// to messages it sends. If one of those messages comes back to it, directly or through other
+// handlers, the call would wait for itself forever. The serial handlers whose calls are being
+// polled are tracked, and locking one of them from inside its own call panics instead.
+//
+// Only messages awaited inside the call are caught. A serial handler that waits for a message sent
+// through a proxy, which the context handles separately, to come back to it still deadlocks.
+
+thread_local! {
+    // the serial handlers being polled on this thread, by the address of their mutex
+    static POLLING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+fn id<H>(handler: &Mutex<H>) -> usize {
+    handler as *const Mutex<H> as usize
+}
+
+// Locks handler, called name, for a call. Panics if the call is being made from inside one of the
+// handler's own calls.
+pub async fn lock<'a, H>(handler: &'a Mutex<H>, name: &str) -> MutexGuard<'a, H> {
+    if POLLING.with_borrow(|polling| polling.contains(&id(handler))) {
+        panic!("Serial handler {} sent a message that came back to it. It handles one message at a time, so it would wait for itself forever", name);
+    }
+    handler.lock().await
+}
+
+// A call of handler, marking it as being polled while the call is.
+pub fn polling<H, F: Future + Unpin>(handler: &Mutex<H>, future: F) -> Polling<F> {
+    Polling { id: id(handler), future }
+}
+
+pub struct Polling<F> {
+    id: usize,
+    future: F,
+}
+
+// pops the handler off POLLING, also when the call panics
+struct Pop;
+
+impl Drop for Pop {
+    fn drop(&mut self) {
+        POLLING.with_borrow_mut(|polling| polling.pop());
+    }
+}
+
+impl<F: Future + Unpin> Future for Polling<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        POLLING.with_borrow_mut(|polling| polling.push(self.id));
+        let _pop = Pop;
+        Pin::new(&mut self.future).poll(cx)
+    }
+}
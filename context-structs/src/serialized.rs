@@ -0,0 +1,224 @@
+use std::fmt::{Debug, Display};
+
+use message_structs::{MessageId, SerdeMessage};
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de::{DeserializeOwned, Error}};
+
+// Messages marked with #[pt_serde] can be turned into a SerializedMessage and their responses
+// into a SerializedResponse. Both are a message id and a payload, where the payload is the
+// message or response encoded in the Format F. Both are themselves serializable, so can be written
+// out with Format::to_wire.
+
+pub trait Format {
+    type Payload: Serialize + DeserializeOwned + Clone + Debug + PartialEq;
+
+    fn to_payload<T: Serialize>(value: &T) -> Result<Self::Payload, SerializeError>;
+    fn from_payload<T: DeserializeOwned>(payload: &Self::Payload) -> Result<T, SerializeError>;
+
+    fn to_wire<T: Serialize>(value: &T) -> Result<Vec<u8>, SerializeError>;
+    fn from_wire<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SerializeError>;
+
+    // How the message id of a SerializedMessage or SerializedResponse is written
+    fn serialize_id<S: Serializer>(id: &MessageId, serializer: S) -> Result<S::Ok, S::Error> {
+        id.serialize(serializer)
+    }
+
+    fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MessageId, D::Error> {
+        MessageId::deserialize(deserializer)
+    }
+}
+
+// Human readable, the payload is a json value so the whole message is readable on the wire. Message
+// ids are strings, as they use all 64 bits and most json readers outside of rust lose the precision
+// of numbers above 2^53.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Json;
+
+impl Format for Json {
+    type Payload = serde_json::Value;
+
+    fn to_payload<T: Serialize>(value: &T) -> Result<Self::Payload, SerializeError> {
+        Ok(serde_json::to_value(value)?)
+    }
+
+    fn from_payload<T: DeserializeOwned>(payload: &Self::Payload) -> Result<T, SerializeError> {
+        Ok(T::deserialize(payload)?)
+    }
+
+    fn to_wire<T: Serialize>(value: &T) -> Result<Vec<u8>, SerializeError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn from_wire<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SerializeError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    fn serialize_id<S: Serializer>(id: &MessageId, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(id)
+    }
+
+    fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MessageId, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        id.parse().map_err(|_| D::Error::invalid_value(serde::de::Unexpected::Str(&id), &"a message id"))
+    }
+}
+
+// Compact, uses bincode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Binary;
+
+impl Format for Binary {
+    type Payload = Vec<u8>;
+
+    fn to_payload<T: Serialize>(value: &T) -> Result<Self::Payload, SerializeError> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn from_payload<T: DeserializeOwned>(payload: &Self::Payload) -> Result<T, SerializeError> {
+        Ok(bincode::deserialize(payload)?)
+    }
+
+    fn to_wire<T: Serialize>(value: &T) -> Result<Vec<u8>, SerializeError> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn from_wire<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SerializeError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[derive(Debug)]
+pub enum SerializeError {
+    // The context has no serializable message with this id
+    UnknownMessage(MessageId),
+    // Tried to decode a message or response as the wrong message type
+    WrongMessage{expected: MessageId, found: MessageId},
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+}
+
+impl Display for SerializeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownMessage(id) => write!(f, "no serializable message with id {}", id),
+            Self::WrongMessage{expected, found} => write!(f, "expected message with id {} but found {}", expected, found),
+            Self::Json(e) => write!(f, "json error: {}", e),
+            Self::Binary(e) => write!(f, "binary error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SerializeError {}
+
+impl From<serde_json::Error> for SerializeError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl From<bincode::Error> for SerializeError {
+    fn from(e: bincode::Error) -> Self {
+        Self::Binary(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct SerializedMessage<F: Format> {
+    #[serde(serialize_with = "F::serialize_id", deserialize_with = "F::deserialize_id")]
+    pub id: MessageId,
+    pub payload: F::Payload,
+}
+
+impl<F: Format> SerializedMessage<F> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SerializeError> {
+        F::to_wire(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SerializeError> {
+        F::from_wire(bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct SerializedResponse<F: Format> {
+    #[serde(serialize_with = "F::serialize_id", deserialize_with = "F::deserialize_id")]
+    pub id: MessageId,
+    pub payload: F::Payload,
+}
+
+impl<F: Format> SerializedResponse<F> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SerializeError> {
+        F::to_wire(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SerializeError> {
+        F::from_wire(bytes)
+    }
+}
+
+fn check_id<M: SerdeMessage>(found: MessageId) -> Result<(), SerializeError> {
+    let expected = M::get_message_spec().id;
+    if expected == found {
+        Ok(())
+    } else {
+        Err(SerializeError::WrongMessage{expected, found})
+    }
+}
+
+pub fn encode_message<F: Format, M: SerdeMessage>(message: &M) -> Result<SerializedMessage<F>, SerializeError> {
+    Ok(SerializedMessage {
+        id: M::get_message_spec().id,
+        payload: F::to_payload(message)?,
+    })
+}
+
+pub fn decode_message<F: Format, M: SerdeMessage>(message: &SerializedMessage<F>) -> Result<M, SerializeError> {
+    check_id::<M>(message.id)?;
+    F::from_payload(&message.payload)
+}
+
+pub fn encode_response<F: Format, M: SerdeMessage>(response: &M::UnwrappedResponse) -> Result<SerializedResponse<F>, SerializeError> {
+    Ok(SerializedResponse {
+        id: M::get_message_spec().id,
+        payload: F::to_payload(response)?,
+    })
+}
+
+pub fn decode_response<F: Format, M: SerdeMessage>(response: &SerializedResponse<F>) -> Result<M::UnwrappedResponse, SerializeError> {
+    check_id::<M>(response.id)?;
+    F::from_payload(&response.payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // above 2^53, so it only survives json as a string
+    const ID: MessageId = u64::MAX - 1;
+
+    #[test]
+    fn json_ids_are_strings() {
+        let message = SerializedMessage::<Json> { id: ID, payload: serde_json::json!({"x": 1}) };
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(String::from_utf8(bytes.clone()).unwrap(), r#"{"id":"18446744073709551614","payload":{"x":1}}"#);
+        assert_eq!(SerializedMessage::<Json>::from_bytes(&bytes).unwrap(), message);
+
+        let response = SerializedResponse::<Json> { id: ID, payload: serde_json::json!(2) };
+        assert_eq!(SerializedResponse::<Json>::from_bytes(&response.to_bytes().unwrap()).unwrap(), response);
+    }
+
+    #[test]
+    fn json_ids_must_be_strings() {
+        assert!(SerializedMessage::<Json>::from_bytes(br#"{"id":1,"payload":null}"#).is_err());
+        assert!(SerializedMessage::<Json>::from_bytes(br#"{"id":"x","payload":null}"#).is_err());
+    }
+
+    #[test]
+    fn binary_ids_are_numbers() {
+        let message = SerializedMessage::<Binary> { id: ID, payload: vec![1, 2] };
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(bytes[..8], ID.to_le_bytes());
+        assert_eq!(SerializedMessage::<Binary>::from_bytes(&bytes).unwrap(), message);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{future::Future, panic::AssertUnwindSafe, time::Instant};
+
+use futures::{StreamExt, stream::LocalBoxStream};
+
+use crate::{HandleError, timeout::{expired, with_deadline}};
+
+// The responses to #[pt_stream] messages sent through a proxy come back over a bounded channel, so
+// a slow reader holds up the handler instead of the items piling up. None marks the end of the
+// stream, if the channel closes without it the context stopped part way through.
+const CAPACITY: usize = 16;
+
+pub type StreamSender<T> = smol::channel::Sender<Option<Result<T, HandleError>>>;
+pub type StreamReceiver<T> = smol::channel::Receiver<Option<Result<T, HandleError>>>;
+
+pub fn channel<T>() -> (StreamSender<T>, StreamReceiver<T>) {
+    smol::channel::bounded(CAPACITY)
+}
+
+// Runs in the context, passes the items of the handler's stream to the proxy. Stops early if the
+// proxy side drops the stream or the deadline passes.
+pub async fn send_items<'a, T>(make_stream: impl FnOnce() -> LocalBoxStream<'a, T>, sender: StreamSender<T>, deadline: Option<Instant>) {
+    if expired(deadline) {
+        let _ = sender.send(Some(Err(HandleError::TimedOut))).await;
+        return;
+    }
+
+    if with_deadline(forward_items(make_stream, &sender), deadline).await.is_err() {
+        let _ = sender.send(Some(Err(HandleError::TimedOut))).await;
+    }
+}
+
+async fn forward_items<'a, T>(make_stream: impl FnOnce() -> LocalBoxStream<'a, T>, sender: &StreamSender<T>) {
+    let stream = match std::panic::catch_unwind(AssertUnwindSafe(make_stream)) {
+        Ok(stream) => stream,
+        Err(_) => {
+            let _ = sender.send(Some(Err(HandleError::HandlerPanicked))).await;
+            return;
+        },
+    };
+
+    let mut stream = AssertUnwindSafe(stream).catch_unwind();
+    while let Some(item) = stream.next().await {
+        let item = item.map_err(|_| HandleError::HandlerPanicked);
+        let panicked = item.is_err();
+        if sender.send(Some(item)).await.is_err() || panicked {
+            return;
+        }
+    }
+    let _ = sender.send(None).await;
+}
+
+enum ReceiveState<F, T> {
+    Sending(F, StreamReceiver<T>),
+    Receiving(StreamReceiver<T>),
+    Done,
+}
+
+async fn receive_next<F, T>(receiver: StreamReceiver<T>) -> Option<(Result<T, HandleError>, ReceiveState<F, T>)> {
+    match receiver.recv().await {
+        Ok(Some(Ok(item))) => Some((Ok(item), ReceiveState::Receiving(receiver))),
+        Ok(Some(Err(e))) => Some((Err(e), ReceiveState::Done)),
+        Ok(None) => None,
+        Err(_) => Some((Err(HandleError::Dropped), ReceiveState::Done)),
+    }
+}
+
+// Runs on the proxy side. send puts the message in the context's queue, then the items arrive on
+// receiver. Any error is the last item of the stream.
+pub fn receive_items<'a, T: 'a>(send: impl Future<Output = Result<(), HandleError>> + 'a, receiver: StreamReceiver<T>) -> LocalBoxStream<'a, Result<T, HandleError>> {
+    futures::stream::unfold(ReceiveState::Sending(send, receiver), |state| async move {
+        match state {
+            ReceiveState::Sending(send, receiver) => match send.await {
+                Ok(()) => receive_next(receiver).await,
+                Err(e) => Some((Err(e), ReceiveState::Done)),
+            },
+            ReceiveState::Receiving(receiver) => receive_next(receiver).await,
+            ReceiveState::Done => None,
+        }
+    }).boxed_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc, time::Duration};
+
+    use smol::{LocalExecutor, future};
+
+    use super::*;
+    use crate::timeout::{deadline, stream_with_deadline};
+
+    // Runs send_items on an executor, as the context does, and collects what the proxy side reads
+    fn round_trip<T: 'static>(make_stream: impl FnOnce() -> LocalBoxStream<'static, T> + 'static, deadline: Option<Instant>) -> Vec<Result<T, HandleError>> {
+        let executor = LocalExecutor::new();
+        let (sender, receiver) = channel();
+        let task = executor.spawn(send_items(make_stream, sender, deadline));
+        let items = future::block_on(executor.run(receive_items(async { Ok(()) }, receiver).collect::<Vec<_>>()));
+        future::block_on(executor.run(task));
+        items
+    }
+
+    #[test]
+    fn items_are_passed_on() {
+        let items = round_trip(|| futures::stream::iter(0..40).boxed_local(), None);
+        assert_eq!(items, (0..40).map(Ok).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn failing_to_send_the_message_is_the_only_item() {
+        let (_, receiver) = channel::<i32>();
+        let items: Vec<_> = future::block_on(receive_items(async { Err(HandleError::Closed) }, receiver).collect());
+        assert_eq!(items, [Err(HandleError::Closed)]);
+    }
+
+    #[test]
+    fn handler_panicking_while_making_the_stream_ends_it() {
+        let items = round_trip::<i32>(|| panic!("no stream"), None);
+        assert_eq!(items, [Err(HandleError::HandlerPanicked)]);
+    }
+
+    #[test]
+    fn handler_panicking_mid_stream_ends_it() {
+        let items = round_trip(|| futures::stream::iter(0..3).map(|i| if i == 2 { panic!("item 2") } else { i }).boxed_local(), None);
+        assert_eq!(items, [Ok(0), Ok(1), Err(HandleError::HandlerPanicked)]);
+    }
+
+    #[test]
+    fn reader_dropping_the_stream_stops_the_handler() {
+        let executor = LocalExecutor::new();
+        let (sender, receiver) = channel();
+        let made = Rc::new(Cell::new(0));
+        let counter = made.clone();
+        let task = executor.spawn(send_items(move || futures::stream::iter(0..).inspect(move |_| counter.set(counter.get() + 1)).boxed_local(), sender, None));
+
+        let mut stream = receive_items(async { Ok(()) }, receiver);
+        assert_eq!(future::block_on(executor.run(stream.next())), Some(Ok(0)));
+        drop(stream);
+        // the channel's capacity and the item that found it closed
+        future::block_on(executor.run(task));
+        assert!(made.get() <= CAPACITY + 2);
+    }
+
+    #[test]
+    fn context_stopping_part_way_through_ends_it_with_dropped() {
+        let executor = LocalExecutor::new();
+        let (sender, receiver) = channel();
+        let task = executor.spawn(send_items(|| futures::stream::iter([1]).chain(futures::stream::pending()).boxed_local(), sender, None));
+
+        let mut stream = receive_items(async { Ok(()) }, receiver);
+        assert_eq!(future::block_on(executor.run(stream.next())), Some(Ok(1)));
+        // as the context drops the handlers it was running when it stops
+        future::block_on(executor.run(task.cancel()));
+        assert_eq!(future::block_on(stream.next()), Some(Err(HandleError::Dropped)));
+        assert_eq!(future::block_on(stream.next()), None);
+    }
+
+    #[test]
+    fn deadline_ends_it_in_the_context() {
+        let items = round_trip(
+            || futures::stream::iter([1]).chain(futures::stream::pending()).boxed_local(),
+            deadline(Some(Duration::from_millis(50))),
+        );
+        assert_eq!(items, [Ok(1), Err(HandleError::TimedOut)]);
+    }
+
+    #[test]
+    fn stream_is_not_made_after_the_deadline() {
+        let items = round_trip::<i32>(|| panic!("made after the deadline"), Some(Instant::now()));
+        assert_eq!(items, [Err(HandleError::TimedOut)]);
+    }
+
+    #[test]
+    fn deadline_ends_it_on_the_proxy_side() {
+        // the context never answers, e.g. as it's busy
+        let (_sender, receiver) = channel::<i32>();
+        let stream = stream_with_deadline(receive_items(async { Ok(()) }, receiver), deadline(Some(Duration::from_millis(50))));
+        let items: Vec<_> = future::block_on(stream.collect());
+        assert_eq!(items, [Err(HandleError::TimedOut)]);
+    }
+}
@@ -0,0 +1,80 @@
+use std::{future::Future, time::{Duration, Instant}};
+
+use futures::{StreamExt, stream::LocalBoxStream};
+
+use crate::HandleError;
+
+// Timeouts are turned into a deadline when the message is sent, so time spent waiting in the
+// context's queue counts towards the timeout.
+pub fn deadline(timeout: Option<Duration>) -> Option<Instant> {
+    timeout.map(|timeout| Instant::now() + timeout)
+}
+
+// The timeout of a message in the context's queue. The sender of a request waits for the response
+// until its deadline, so that is fixed when it's sent. No one waits for a posted message, so its
+// timeout starts when the context takes it from the queue, e.g. once the context runs for messages
+// posted during init.
+#[derive(Debug, Clone, Copy)]
+pub enum QueuedDeadline {
+    At(Instant),
+    AfterDequeue(Duration),
+}
+
+impl QueuedDeadline {
+    pub fn posted(timeout: Option<Duration>) -> Option<Self> {
+        timeout.map(Self::AfterDequeue)
+    }
+
+    // The deadline, called when the message is taken from the queue
+    pub fn dequeued(deadline: Option<Self>) -> Option<Instant> {
+        deadline.map(|deadline| match deadline {
+            Self::At(deadline) => deadline,
+            Self::AfterDequeue(timeout) => Instant::now() + timeout,
+        })
+    }
+}
+
+pub fn expired(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| deadline <= Instant::now())
+}
+
+// TimedOut if future hasn't finished by the deadline. The future is dropped, which cancels it, and
+// isn't polled at all if the deadline has already passed.
+pub async fn with_deadline<F: Future>(future: F, deadline: Option<Instant>) -> Result<F::Output, HandleError> {
+    match deadline {
+        None => Ok(future.await),
+        Some(_) if expired(deadline) => Err(HandleError::TimedOut),
+        Some(deadline) => smol::future::or(
+            async { Ok(future.await) },
+            async {
+                smol::Timer::at(deadline).await;
+                Err(HandleError::TimedOut)
+            },
+        ).await,
+    }
+}
+
+// Ends the stream with TimedOut if it hasn't ended by the deadline.
+pub fn stream_with_deadline<'a, T: 'a>(stream: LocalBoxStream<'a, Result<T, HandleError>>, deadline: Option<Instant>) -> LocalBoxStream<'a, Result<T, HandleError>> {
+    let Some(deadline) = deadline else {
+        return stream;
+    };
+
+    futures::stream::unfold(Some((stream, smol::Timer::at(deadline))), |state| async move {
+        let (mut stream, mut timer) = state?;
+        let next = smol::future::or(
+            async { Some(stream.next().await) },
+            async {
+                (&mut timer).await;
+                None
+            },
+        ).await;
+
+        match next {
+            Some(Some(Ok(item))) => Some((Ok(item), Some((stream, timer)))),
+            Some(Some(Err(e))) => Some((Err(e), None)),
+            Some(None) => None,
+            None => Some((Err(HandleError::TimedOut), None)),
+        }
+    }).boxed_local()
+}
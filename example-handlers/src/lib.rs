@@ -1,20 +1,26 @@
-use std::time::Duration;
+use std::{time::Duration, cell::{Cell, RefCell}, sync::Arc, marker::PhantomData};
 
-use example_messages::{Add1, Times3, Add2, GetExampleInitValue, NoResponse};
-use futures::FutureExt;
-use handler_proc_macros::Handler;
-use handler_structs::{Handle, HandlerInit};
+use example_messages::{Add1, Times3, Add2, GetExampleInitValue, NoResponse, Echo, EditCommand, MoveCursor, FindInDocument, MemoryUsage, FileType, DocumentChanged, PluginLoaded};
+use futures::{FutureExt, StreamExt};
+use handler_proc_macros::{Handler, Agent};
+use handler_structs::{Handle, HandlerInit, HandlerLink, HandlerShutdown};
 use message_list::C;
-use context_structs::CtxHandle;
+use serde::{Deserialize, Serialize};
+use context_structs::{CtxHandle, CtxPost, call};
+use message_structs::TypePath;
 
 
 #[derive(Handler)]
-#[pt_handles(GetExampleInitValue, NoResponse)]
+#[pt_handles(GetExampleInitValue, NoResponse, DocumentChanged, PluginLoaded)]
+#[pt_link]
 pub struct SomeInitHandler {}
 
 impl Handle<GetExampleInitValue> for SomeInitHandler {
     fn handle<'a>(&'a self, _ctx: &'a impl C, _message: GetExampleInitValue) -> <GetExampleInitValue as message_structs::Message>::Response<'a> {
-        42
+        async {
+            smol::Timer::after(Duration::from_millis(10)).await;
+            42
+        }.boxed_local()
     }
 }
 
@@ -27,58 +33,240 @@ impl Handle<NoResponse> for SomeInitHandler {
     }
 }
 
+impl Handle<DocumentChanged> for SomeInitHandler {
+    fn handle<'a>(&'a self, _ctx: &'a impl C, message: Arc<DocumentChanged>) -> <DocumentChanged as message_structs::Message>::HandlerResponse<'a> {
+        println!("SomeInitHandler document changed, {} chars", message.text.chars().count());
+    }
+}
+
+impl Handle<PluginLoaded> for SomeInitHandler {
+    fn handle<'a>(&'a self, _ctx: &'a impl C, message: PluginLoaded) -> <PluginLoaded as message_structs::Message>::Response<'a> {
+        println!("SomeInitHandler saw {} loaded", message.name);
+    }
+}
+
+// ArithmeticHandler needs this handler during init, so it can only be asked once everything is
+// initialised
+impl HandlerLink for SomeInitHandler {
+    fn link<'a>(&'a self, ctx: &'a impl C) -> futures::future::LocalBoxFuture<'a, ()> {
+        async move {
+            println!("SomeInitHandler linked, Add1 gives {}", call!(ctx, Add1{ x: 41 }));
+        }.boxed_local()
+    }
+}
+
 impl HandlerInit for SomeInitHandler {
-    fn init<'a, Ctx: C + 'a>(_ctx: &Self::InitCtx<'a, Ctx>, _config: Self::InitConfig) -> Self {
+    type InitResult = Self;
+
+    async fn init<'a, Ctx: C + 'a>(_ctx: &'a Self::InitCtx<'a, Ctx>, _config: Self::InitConfig) -> Self {
         Self {}
     }
 }
 
 
+// Loaded by ContextConfig's loader, any field that isn't set keeps its default
+#[derive(Serialize, Deserialize, Default)]
 pub struct Config {
     pub hello: bool,
 }
 
-#[derive(Handler)]
-#[pt_handles(Add1, Times3, Add2, NoResponse)]
-#[pt_config(Config)]
-#[pt_init(GetExampleInitValue)]
 pub struct ArithmeticHandler {}
 
-
-impl HandlerInit for ArithmeticHandler {
-    fn init<'a, Ctx: C + 'a>(ctx: &Self::InitCtx<'a, Ctx>, config: Self::InitConfig) -> Self {
-        let init_val = ctx.handle(GetExampleInitValue{});
+#[Agent]
+impl ArithmeticHandler {
+    async fn init(ctx: &impl CtxHandle<GetExampleInitValue>, config: Config) -> Self {
+        let init_val = ctx.handle(GetExampleInitValue{}).await;
         println!("ArithmeticHandler init, hello={} init_val={}", config.hello, init_val);
         Self {}
     }
+
+    fn add1(&self, message: Add1) -> i32 {
+        self.add(message.x, 1)
+    }
+
+    #[pt_skip]
+    fn add(&self, x: i32, y: i32) -> i32 {
+        x + y
+    }
+
+    async fn times3(&self, message: Times3) -> i32 {
+        message.x * 3
+    }
+
+    fn add2(&self, ctx: &impl C, message: Add2) -> i32 {
+        let add1 = call!(ctx, Add1{ x: message.x });
+        let add2 = call!(ctx, Add1{ x: add1 });
+        add2
+    }
+
+    async fn no_response(&self, message: NoResponse) {
+        smol::Timer::after(Duration::from_secs(2)).await;
+        println!("NoResponse handler got message: {:?}", message.x);
+    }
+
+    fn memory_usage(&self, _message: MemoryUsage) -> usize {
+        std::mem::size_of::<Self>()
+    }
+
+    fn file_type(&self, message: FileType) -> Option<String> {
+        message.path.ends_with(".calc").then(|| "calculation".to_string())
+    }
+}
+
+
+// Takes &mut self, so it's serial and counts the messages without a Cell
+#[derive(Default)]
+pub struct NoResponseLog {
+    count: usize,
 }
 
+#[Agent]
+impl NoResponseLog {
+    async fn no_response(&mut self, message: NoResponse) {
+        self.count += 1;
+        println!("NoResponseLog message {} was {:?}", self.count, message.x);
+    }
 
-impl Handle<Add1> for ArithmeticHandler {
-    fn handle<'a>(&'a self, _ctx: &impl C, message: Add1) -> <Add1 as message_structs::Message>::Response<'a> {
-        message.x + 1
+    async fn shutdown(&self) {
+        println!("NoResponseLog shut down after {} messages", self.count);
     }
 }
 
-impl Handle<Times3> for ArithmeticHandler {
-    fn handle<'a>(&'a self, _ctx: &'a impl C, message: Times3) -> <Times3 as message_structs::Message>::Response<'a> {
-        async move {message.x * 3}.boxed()
+
+// Generic handlers are listed in the context with their type arguments, e.g. EchoHandler<i32>
+pub struct EchoHandler<T> {
+    value: PhantomData<T>,
+}
+
+#[Agent]
+impl<T: TypePath + 'static> EchoHandler<T> {
+    fn init() -> Self {
+        Self {value: PhantomData}
+    }
+
+    fn echo(&self, message: Echo<T>) -> T {
+        message.value
     }
 }
 
-impl Handle<Add2> for ArithmeticHandler {
-    fn handle<'a>(&'a self, ctx: &'a impl C, message: Add2) -> <Add2 as message_structs::Message>::Response<'a> {
-        let add1 = ctx.handle(Add1{ x: message.x });
-        let add2 = ctx.handle(Add1{ x: add1 });
-        add2
+
+#[derive(Handler)]
+#[pt_handles(EditCommand, MoveCursor, FindInDocument, MemoryUsage, FileType)]
+#[pt_shutdown]
+pub struct Document {
+    text: RefCell<String>,
+    cursor: Cell<usize>,
+}
+
+impl HandlerInit for Document {
+    type InitResult = Self;
+
+    async fn init<'a, Ctx: C + 'a>(ctx: &'a Self::InitCtx<'a, Ctx>, _config: Self::InitConfig) -> Self {
+        // SomeInitHandler gets this once the context runs, whichever of the two is initialised first
+        ctx.post(PluginLoaded{ name: "document".to_string() }).unwrap();
+        Self {text: RefCell::new(String::new()), cursor: Cell::new(0)}
     }
 }
 
-impl Handle<NoResponse> for ArithmeticHandler {
-    fn handle<'a>(&'a self, _ctx: &'a impl C, message: NoResponse) -> <NoResponse as message_structs::Message>::Response<'a> {
+impl Document {
+    fn byte_index(text: &str, char_index: usize) -> usize {
+        text.char_indices().nth(char_index).map(|(i, _)| i).unwrap_or(text.len())
+    }
+}
+
+impl Handle<EditCommand> for Document {
+    fn handle<'a>(&'a self, ctx: &'a impl C, message: EditCommand) -> <EditCommand as message_structs::Message>::Response<'a> {
         async move {
-            smol::Timer::after(Duration::from_secs(2)).await;
-            println!("NoResponse handler got message: {:?}", message.x);
-        }.boxed()
+            let len = self.edit(message);
+            call!(ctx, DocumentChanged{ text: self.text.borrow().clone() });
+            len
+        }.boxed_local()
+    }
+}
+
+impl Document {
+    fn edit(&self, message: EditCommand) -> usize {
+        let mut text = self.text.borrow_mut();
+        match message {
+            EditCommand::Insert { at, text: inserted } => {
+                let at = Self::byte_index(&text, at);
+                text.insert_str(at, &inserted);
+            },
+            EditCommand::Delete { at, len } => {
+                let start = Self::byte_index(&text, at);
+                let end = Self::byte_index(&text, at + len);
+                text.replace_range(start..end, "");
+            },
+            EditCommand::Clear => text.clear(),
+        }
+        let len = text.chars().count();
+        self.cursor.set(self.cursor.get().min(len));
+        len
+    }
+}
+
+impl Handle<MoveCursor> for Document {
+    fn handle<'a>(&'a self, _ctx: &'a impl C, message: MoveCursor) -> <MoveCursor as message_structs::Message>::Response<'a> {
+        let len = self.text.borrow().chars().count();
+        let cursor = self.cursor.get();
+        self.cursor.set(match message {
+            MoveCursor::Left(n) => cursor.saturating_sub(n),
+            MoveCursor::Right(n) => (cursor + n).min(len),
+            MoveCursor::Start => 0,
+            MoveCursor::End => len,
+        });
+        println!("Document cursor moved {:?} to {}", message, self.cursor.get());
+    }
+}
+
+impl Handle<FindInDocument> for Document {
+    fn handle<'a>(&'a self, _ctx: &'a impl C, message: FindInDocument) -> <FindInDocument as message_structs::Message>::Response<'a> {
+        // collected up front so the text isn't borrowed while the stream is being read
+        let text = self.text.borrow();
+        let matches: Vec<usize> = text.match_indices(&message.pattern)
+            .map(|(i, _)| text[..i].chars().count())
+            .collect();
+        futures::stream::iter(matches).boxed_local()
+    }
+}
+
+impl Handle<MemoryUsage> for Document {
+    fn handle<'a>(&'a self, _ctx: &'a impl C, _message: MemoryUsage) -> <MemoryUsage as message_structs::Message>::HandlerResponse<'a> {
+        std::mem::size_of::<Self>() + self.text.borrow().capacity()
+    }
+}
+
+impl Handle<FileType> for Document {
+    fn handle<'a>(&'a self, _ctx: &'a impl C, message: FileType) -> <FileType as message_structs::Message>::HandlerResponse<'a> {
+        message.path.ends_with(".txt").then(|| "text document".to_string())
+    }
+}
+
+impl HandlerShutdown for Document {
+    fn shutdown<'a>(&'a self, ctx: &'a impl C) -> futures::future::LocalBoxFuture<'a, ()> {
+        async move {
+            // the handlers before this one are still running
+            let memory_usage = call!(ctx, MemoryUsage{});
+            println!("Document shut down with {:?}, memory usage {}", self.text.borrow(), memory_usage);
+        }.boxed_local()
+    }
+}
+
+
+#[derive(Handler)]
+#[pt_handles(DocumentChanged)]
+pub struct WordCounter {}
+
+impl HandlerInit for WordCounter {
+    type InitResult = Self;
+
+    async fn init<'a, Ctx: C + 'a>(_ctx: &'a Self::InitCtx<'a, Ctx>, _config: Self::InitConfig) -> Self {
+        Self {}
+    }
+}
+
+impl Handle<DocumentChanged> for WordCounter {
+    fn handle<'a>(&'a self, _ctx: &'a impl C, message: Arc<DocumentChanged>) -> <DocumentChanged as message_structs::Message>::HandlerResponse<'a> {
+        println!("WordCounter document changed, {} words", message.text.split_whitespace().count());
     }
 }
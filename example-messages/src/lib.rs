@@ -1,20 +1,30 @@
 use message_proc_macros::Message;
+use serde::{Serialize, Deserialize};
 
-#[derive(Message)]
+// lets message_list! include every message here with example_messages::*
+include!(concat!(env!("OUT_DIR"), "/message_registry.rs"));
+
+/// Responds with x + 1
+#[derive(Message, Serialize, Deserialize)]
+#[pt_serde]
 #[pt_response(i32)]
 #[pt_sync]
 pub struct Add1 {
+    /// The number to add to
     pub x: i32
 }
 
-#[derive(Message)]
+/// Responds with x * 3
+#[derive(Message, Serialize, Deserialize)]
+#[pt_serde]
 #[pt_response(i32)]
 pub struct Times3 {
     pub x: i32
 }
 
 
-#[derive(Message)]
+#[derive(Message, Serialize, Deserialize)]
+#[pt_serde]
 #[pt_response(i32)]
 #[pt_sync]
 pub struct Add2 {
@@ -23,11 +33,102 @@ pub struct Add2 {
 
 #[derive(Message)]
 #[pt_response(i32)]
-#[pt_sync]
 pub struct GetExampleInitValue {}
 
 
-#[derive(Clone, Message)]
+#[derive(Clone, Message, Serialize, Deserialize)]
+#[pt_serde]
+#[pt_timeout(5000)]
 pub struct NoResponse {
     pub x: i32
 }
+
+/// Done after ms milliseconds. Gives up after 100ms, so through try_handle or post longer waits
+/// time out.
+#[derive(Message)]
+#[pt_timeout(100)]
+pub struct Wait {
+    pub ms: u64
+}
+
+
+// Generic messages are instantiated in message_list!, each instantiation is a separate message.
+#[derive(Message, Serialize, Deserialize)]
+#[pt_serde]
+#[pt_response(T)]
+#[pt_sync]
+pub struct Echo<T> {
+    pub value: T
+}
+
+
+/// Edits the example document, responds with the new length of the document
+#[derive(Message, Serialize, Deserialize)]
+#[pt_serde]
+#[pt_response(usize)]
+pub enum EditCommand {
+    /// Inserts text before the char at index at
+    Insert { at: usize, text: String },
+    /// Deletes len chars starting at index at
+    Delete { at: usize, len: usize },
+    Clear,
+}
+
+/// Every handler responds with the number of bytes it is using, the response is the total
+#[derive(Message, Clone)]
+#[pt_response(usize)]
+#[pt_collect(Sum)]
+#[pt_sync]
+pub struct MemoryUsage {}
+
+/// Asks the handlers in turn for the type of the file at path
+#[derive(Message, Clone)]
+#[pt_response(String)]
+#[pt_first_match]
+#[pt_sync]
+pub struct FileType {
+    pub path: String,
+}
+
+/// Checks the spelling of word. No handler in the example context handles it, so the response is
+/// always None.
+#[derive(Message)]
+#[pt_response(bool)]
+#[pt_optional]
+#[pt_sync]
+pub struct SpellCheck {
+    pub word: String,
+}
+
+/// Streams the char index of every match of pattern in the example document
+#[derive(Message)]
+#[pt_stream(usize)]
+pub struct FindInDocument {
+    pub pattern: String,
+}
+
+/// Sent by the example document after every edit, with the whole text. Handlers share one copy.
+#[derive(Message)]
+#[pt_shared]
+#[pt_sync]
+pub struct DocumentChanged {
+    pub text: String,
+}
+
+/// Sent by handlers that are ready to be used, with their name. Posted during init, so it is only
+/// handled once the context runs.
+#[derive(Message)]
+#[pt_sync]
+pub struct PluginLoaded {
+    pub name: String,
+}
+
+/// Moves the cursor in the example document
+#[derive(Message, Clone, Debug)]
+#[pt_sync]
+pub enum MoveCursor {
+    Left(usize),
+    Right(usize),
+    Start,
+    End,
+}
@@ -5,10 +5,18 @@ use application::*;
 define_context_type!{
     Messages: messages
     Handlers: {
-        init: example_handlers::SomeInitHandler,
+        // first so that it is shut down last
+        exit: ExitHandler,
+
+        // initialised after init, which answers its init request
         arithmetic: example_handlers::ArithmeticHandler,
+        init: example_handlers::SomeInitHandler,
+        no_response_log: example_handlers::NoResponseLog,
+        echo_i32: example_handlers::EchoHandler<i32>,
+        echo_string: example_handlers::EchoHandler<String>,
+        document: example_handlers::Document,
+        word_counter: example_handlers::WordCounter,
 
         windows: Windows,
-        exit: ExitHandler,
     }
 }
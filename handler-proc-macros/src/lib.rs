@@ -1,6 +1,6 @@
 use proc_macro2::{TokenStream, Span, Ident};
-use syn::{parse_macro_input, Generics, DeriveInput, Attribute};
-use quote::quote;
+use syn::{parse_macro_input, parse_quote, Generics, GenericParam, DeriveInput, Attribute, ItemImpl, ImplItem, FnArg, Type, TypeParamBound, PathArguments, GenericArgument, spanned::Spanned};
+use quote::{quote, quote_spanned};
 use proc_macro_helpers::{BareList};
 
 fn get_attribute<'a>(attrs: &'a [Attribute], to_find: &str) -> Option<&'a Attribute> {
@@ -12,7 +12,7 @@ fn get_attribute<'a>(attrs: &'a [Attribute], to_find: &str) -> Option<&'a Attrib
             }
         }
     }
-    return None;
+    None
 }
 
 fn get_handled_messages(attrs: &[Attribute]) -> Option<syn::Result<BareList<syn::Type>>> {
@@ -38,9 +38,12 @@ fn get_init_config(attrs: &[Attribute]) -> Option<syn::Result<syn::Type>> {
     })
 }
 
-fn assert_not_generic(ast: &DeriveInput) {
-    if ast.generics != Generics::default() {
-        panic!("Generic handlers are not supported");
+// Lifetime and const parameters can't be recovered from the HandlerSpec name, so only type
+// parameters are supported.
+fn check_generics(generics: &Generics) -> syn::Result<()> {
+    match generics.params.iter().find(|param| !matches!(param, GenericParam::Type(_))) {
+        Some(param) => Err(syn::Error::new(param.span(), "Handlers can only be generic over types")),
+        None => Ok(()),
     }
 }
 
@@ -55,24 +58,67 @@ fn invert_option_result<T, E>(a: Option<Result<T, E>>) -> Result<Option<T>, E> {
     }
 }
 
-fn try_handler_macro(ast: DeriveInput) -> syn::Result<TokenStream> {
-    let ident = ast.ident;
+// What the Handler impl is generated from, given by the attributes of #[derive(Handler)] or worked
+// out from the methods by #[Agent]
+struct HandlerDecl {
+    handled_messages: Vec<syn::Type>,
+    init_requests: Vec<syn::Type>,
+    init_config: Option<syn::Type>,
+    is_serial: bool,
+    has_link: bool,
+    has_shutdown: bool,
+}
 
+fn try_handler_macro(ast: DeriveInput) -> syn::Result<TokenStream> {
     let handled_messages: Vec<_> = invert_option_result(get_handled_messages(&ast.attrs))?
         .into_iter()
-        .map(|p| p.values)
-        .flatten()
+        .flat_map(|p| p.values)
         .collect();
 
     let init_requests: Vec<_> = invert_option_result(get_init_requests(&ast.attrs))?
         .into_iter()
-        .map(|p| p.values)
-        .flatten()
+        .flat_map(|p| p.values)
         .collect();
 
     let init_config = invert_option_result(get_init_config(&ast.attrs))?;
 
+    // pt_serial handlers implement HandleMut instead of Handle
+    let is_serial = get_attribute(&ast.attrs, "pt_serial").is_some();
+
+    // pt_link handlers implement HandlerLink and pt_shutdown handlers HandlerShutdown
+    let has_link = get_attribute(&ast.attrs, "pt_link").is_some();
+    let has_shutdown = get_attribute(&ast.attrs, "pt_shutdown").is_some();
+
+    check_generics(&ast.generics)?;
+
+    let ident = &ast.ident;
+    let (_, ty_generics, _) = ast.generics.split_for_impl();
+    let self_ty: Type = parse_quote!(#ident #ty_generics);
+    let type_args: Vec<Type> = ast.generics.type_params().map(|param| {
+        let ident = &param.ident;
+        parse_quote!(#ident)
+    }).collect();
+
+    let decl = HandlerDecl { handled_messages, init_requests, init_config, is_serial, has_link, has_shutdown };
+    Ok(make_handler_impl(&self_ty, ident, &type_args, &ast.generics, decl))
+}
+
+// The Handler impl and everything that goes with it, shared by #[derive(Handler)] and #[Agent].
+// self_ty is ident with type_args, which are the impl's type parameters or, for an #[Agent] impl
+// of e.g. EchoHandler<i32>, concrete types.
+fn make_handler_impl(self_ty: &Type, ident: &Ident, type_args: &[Type], generics: &Generics, decl: HandlerDecl) -> TokenStream {
+    let HandlerDecl { handled_messages, init_requests, init_config, is_serial, has_link, has_shutdown } = decl;
     let has_init_config = init_config.is_some();
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+    // the type arguments of a generic handler go in its name, so they have to implement TypePath
+    let mut handler_generics = generics.clone();
+    for param in generics.type_params() {
+        let ident = &param.ident;
+        handler_generics.make_where_clause().predicates.push(parse_quote!(#ident: ::message_structs::TypePath));
+    }
+    let handler_where_clause = &handler_generics.where_clause;
+
 
     let init_config_type_snippet = if let Some(init_config) = init_config {
         quote!(type InitConfig = #init_config;)
@@ -80,45 +126,97 @@ fn try_handler_macro(ast: DeriveInput) -> syn::Result<TokenStream> {
         quote!(type InitConfig = ();)
     };
 
-    let impl_init_ctx_struct_snippet = if init_requests.len() > 0 {
-        quote!(
-            pub struct InitCtx<'a, Ctx> where Ctx: C, Ctx: 'a {
-                // TODO make this private
-                pub ctx: &'a Ctx,
-            }
-    
-            // implement Handle for each init request
-            #(
-                impl<'a, Ctx> ::context_structs::CtxHandle<#init_requests> for InitCtx<'a, Ctx> where Ctx: C, Ctx: 'a {
-                    fn handle<'b>(&'b self, message: #init_requests) -> <#init_requests as ::message_structs::Message>::Response<'b> {
-                        self.ctx.handle(message)
-                    }
+    // a message that can't be answered until the context runs can't be an init request. Checked
+    // here so the error points at the request in the handler, context_type! can only point at itself.
+    let init_request_checks = init_requests.iter().map(|request| {
+        let error = format!("{} can't ask for {} during init, as it's marked #[pt_not_during_init]", ident, quote!(#request).to_string().replace(' ', ""));
+        quote_spanned!(request.span()=> const _: () = assert!(<#request as ::message_structs::Message>::AVAILABLE_DURING_INIT, #error);)
+    });
+
+    let impl_init_ctx_struct_snippet = quote!(
+        pub struct InitCtx<'a, Ctx> where Ctx: C, Ctx: 'a {
+            // TODO make this private
+            pub ctx: &'a Ctx,
+        }
+
+        // implement Handle for each init request. These can't depend on the type parameters
+        // of a generic handler, as only the messages in the context can be sent.
+        #(
+            impl<'a, Ctx> ::context_structs::CtxHandle<#init_requests> for InitCtx<'a, Ctx> where Ctx: C, Ctx: 'a {
+                fn handle<'b>(&'b self, message: #init_requests) -> <#init_requests as ::message_structs::Message>::Response<'b> {
+                    self.ctx.handle(message)
                 }
-            )*
-        )
+            }
+        )*
+
+        // any message can be posted, it waits in the context's queue until the context runs
+        impl<'a, Ctx, M> ::context_structs::CtxPost<M> for InitCtx<'a, Ctx> where Ctx: C + ::context_structs::CtxPost<M>, Ctx: 'a, M: ::message_structs::Message {
+            fn post(&self, message: M) -> ::std::result::Result<(), ::context_structs::HandleError> {
+                self.ctx.post(message)
+            }
+        }
+
+        impl<'a, Ctx> InitCtx<'a, Ctx> where Ctx: C, Ctx: 'a {
+            // Messages sent through the proxy are only handled once the context runs. Requests
+            // through it fail with HandleError::NotRunning on this thread until then, or panic
+            // through handle, other threads can wait for the response.
+            pub fn proxy(&self) -> ::std::boxed::Box<dyn C + Send> {
+                self.ctx.proxy()
+            }
+        }
+    );
+
+    // the type arguments are in the name so every impl of a generic handler gets its own module
+    let hidden_mod = if type_args.is_empty() {
+        format!("_pt_{}", ident)
     } else {
-        quote!()
+        let type_args = quote!(#(#type_args)_*).to_string();
+        let type_args: String = type_args.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+        format!("_pt_{}_{}", ident, type_args.split('_').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("_"))
     };
+    let hidden_mod = Ident::new(&hidden_mod, Span::call_site());
 
-    let hidden_mod = Ident::new(&format!("_pt_{}", ident), Span::call_site());
+    let init_ctx_struct_snippet = quote!(
+        type InitCtx<'a, Ctx> = #hidden_mod::InitCtx<'a, Ctx> where Self: 'a, Ctx: C, Ctx: 'a;
+    );
 
-    let init_ctx_struct_snippet = if init_requests.len() > 0 {
-        quote!(
-            type InitCtx<'a, Ctx> = #hidden_mod::InitCtx<'a, Ctx> where Self: 'a, Ctx: C, Ctx: 'a;
-        )
+
+    let name_snippet = if type_args.is_empty() {
+        quote!(concat!("::", module_path!(), "::", stringify!(#ident)))
     } else {
-        quote!(
-            type InitCtx<'a, Ctx> = () where Self: 'a, Ctx: C, Ctx: 'a;
-        )
+        // the paths of the type arguments, as for generic messages. get_handler_spec is only
+        // called once per handler while building the context, so the name is leaked.
+        quote!({
+            let type_args: &[::std::string::String] = &[#( <#type_args as ::message_structs::TypePath>::type_path() ),*];
+            let name = format!("{}<{}>", concat!("::", module_path!(), "::", stringify!(#ident)), type_args.join(", "));
+            ::std::boxed::Box::leak(name.into_boxed_str())
+        })
+    };
+
+    // handlers without #[pt_link] or #[pt_shutdown] get the default that does nothing, so an impl
+    // without the attribute conflicts with it rather than never being called
+    let default_link_impl = if has_link {
+        quote!()
+    } else {
+        quote!(impl #impl_generics ::handler_structs::HandlerLink for #self_ty #handler_where_clause {})
+    };
+    let default_shutdown_impl = if has_shutdown {
+        quote!()
+    } else {
+        quote!(impl #impl_generics ::handler_structs::HandlerShutdown for #self_ty #handler_where_clause {})
     };
 
+    quote!(
+        #default_link_impl
+
+        #default_shutdown_impl
 
-    Ok(quote!(
         mod #hidden_mod {
             use super::*;
+            #(#init_request_checks)*
             #impl_init_ctx_struct_snippet
         }
-        impl ::handler_structs::Handler for #ident {
+        impl #impl_generics ::handler_structs::Handler for #self_ty #handler_where_clause {
             #init_config_type_snippet
             #init_ctx_struct_snippet
 
@@ -127,18 +225,21 @@ fn try_handler_macro(ast: DeriveInput) -> syn::Result<TokenStream> {
                 let init_requests: &[& 'static ::message_structs::MessageSpec] = &[#(<#init_requests as ::message_structs::Message>::get_message_spec()),*];
 
                 let handled_messages_in_context = handled_messages.into_iter()
-                    .filter(|spec| messages_in_context.iter().any(|o| o.name == spec.name))
+                    .filter(|spec| messages_in_context.iter().any(|o| o.id == spec.id))
                     .map(|spec| *spec);
 
                 let init_requests_in_context = init_requests.into_iter()
-                    .filter(|spec| messages_in_context.iter().any(|o| o.name == spec.name))
+                    .filter(|spec| messages_in_context.iter().any(|o| o.id == spec.id))
                     .map(|spec| *spec);
 
                 ::handler_structs::HandlerSpec {
-                    name: concat!("::", module_path!(), "::", stringify!(#ident)),
+                    name: #name_snippet,
                     handled_messages: handled_messages_in_context.collect(),
                     init_requests: init_requests_in_context.collect(),
                     has_init_config: #has_init_config,
+                    is_serial: #is_serial,
+                    has_link: #has_link,
+                    has_shutdown: #has_shutdown,
                     span: proc_macro2::Span::call_site(),
                 }
             }
@@ -146,18 +247,16 @@ fn try_handler_macro(ast: DeriveInput) -> syn::Result<TokenStream> {
 
         #(
 
-        impl ::handler_structs::hidden::DeclaredHandle<#handled_messages> for #ident {}
+        impl #impl_generics ::handler_structs::hidden::DeclaredHandle<#handled_messages> for #self_ty #where_clause {}
 
         )*
-    ))
+    )
 }
 
-#[proc_macro_derive(Handler, attributes(pt_handles, pt_init, pt_config))]
+#[proc_macro_derive(Handler, attributes(pt_handles, pt_init, pt_config, pt_serial, pt_link, pt_shutdown))]
 pub fn handler_macro(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast = parse_macro_input!(item as DeriveInput);
 
-    assert_not_generic(&ast);
-
     match try_handler_macro(ast) {
         Ok(ts) => ts.into(),
         Err(e) => e.to_compile_error().into(),
@@ -165,6 +264,283 @@ pub fn handler_macro(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
 }
 
 
+// The type in the angle brackets of e.g. CtxHandle<A> or Arc<A>
+fn single_type_arg(segment: &syn::PathSegment) -> Option<&Type> {
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The init requests of an init context parameter, &impl CtxHandle<A> + CtxHandle<B>. None if the
+// parameter isn't an init context.
+fn get_ctx_handle_bounds(ty: &Type) -> Option<syn::Result<Vec<Type>>> {
+    let Type::Reference(reference) = ty else {
+        return None;
+    };
+    let Type::ImplTrait(impl_trait) = &*reference.elem else {
+        return None;
+    };
+
+    Some(impl_trait.bounds.iter()
+        .filter_map(|bound| match bound {
+            TypeParamBound::Trait(bound) => Some(bound),
+            _ => None,
+        })
+        .map(|bound| {
+            bound.path.segments.last()
+                .filter(|segment| segment.ident == "CtxHandle")
+                .and_then(single_type_arg)
+                .cloned()
+                .ok_or_else(|| syn::Error::new(bound.span(), "The init context can only be bounded by CtxHandle<Request> for the requests needed during init"))
+        })
+        .collect())
+}
+
+// The message a handler method takes, and whether it takes it in an Arc as for pt_shared messages
+fn get_message_type(ty: &Type) -> (Type, bool) {
+    if let Type::Path(path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            if segment.ident == "Arc" {
+                if let Some(message) = single_type_arg(segment) {
+                    return (message.clone(), true);
+                }
+            }
+        }
+    }
+    (ty.clone(), false)
+}
+
+fn try_agent_macro(mut item: ItemImpl) -> syn::Result<TokenStream> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(syn::Error::new(path.span(), "#[Agent] goes on an inherent impl block"));
+    }
+    check_generics(&item.generics)?;
+    let generics = item.generics.clone();
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let self_ty = (*item.self_ty).clone();
+    let segment = match &self_ty {
+        Type::Path(path) => path.path.segments.last(),
+        _ => None,
+    }.ok_or_else(|| syn::Error::new(self_ty.span(), "#[Agent] needs a named handler type"))?;
+    let ident = segment.ident.clone();
+    // the impl's type parameters, e.g. impl<T> EchoHandler<T>, or concrete types, e.g. EchoHandler<i32>
+    let type_args = match &segment.arguments {
+        PathArguments::None => Vec::new(),
+        PathArguments::AngleBracketed(args) => args.args.iter().map(|arg| match arg {
+            GenericArgument::Type(ty) => Ok(ty.clone()),
+            _ => Err(syn::Error::new(arg.span(), "Handlers can only be generic over types")),
+        }).collect::<syn::Result<_>>()?,
+        PathArguments::Parenthesized(args) => return Err(syn::Error::new(args.span(), "#[Agent] needs a named handler type")),
+    };
+
+    let mut handled_messages = Vec::new();
+    let mut calls = Vec::new();
+    let mut is_serial = false;
+    let mut init_requests = Vec::new();
+    let mut init_config = None;
+    let mut init_fn = None;
+    let mut init_output = None;
+    let mut link_fn = None;
+    let mut shutdown_fn = None;
+
+    for impl_item in &mut item.items {
+        let ImplItem::Fn(method) = impl_item else {
+            continue;
+        };
+        // helper methods marked #[pt_skip] aren't handlers, the attribute is only for #[Agent]
+        if get_attribute(&method.attrs, "pt_skip").is_some() {
+            method.attrs.retain(|attr| !attr.path().is_ident("pt_skip"));
+            continue;
+        }
+        let sig = &method.sig;
+        let name = &sig.ident;
+
+        if sig.receiver().is_none() {
+            if name != "init" {
+                continue;
+            }
+            // fn init(ctx: &impl CtxHandle<A> + CtxHandle<B>, config: Config) -> Self
+            let mut args = Vec::new();
+            for arg in &sig.inputs {
+                let FnArg::Typed(arg) = arg else {
+                    continue;
+                };
+                if let Some(requests) = get_ctx_handle_bounds(&arg.ty) {
+                    init_requests.extend(requests?);
+                    args.push(quote!(ctx));
+                } else if init_config.is_none() {
+                    init_config = Some((*arg.ty).clone());
+                    args.push(quote!(config));
+                } else {
+                    return Err(syn::Error::new(arg.span(), "init takes at most an init context and a config"));
+                }
+            }
+            init_output = Some(sig.output.clone());
+            init_fn = Some(if sig.asyncness.is_some() {
+                quote!(<#self_ty>::init(#(#args),*).await)
+            } else {
+                quote!(<#self_ty>::init(#(#args),*))
+            });
+            continue;
+        }
+
+        // async fn link(&self, ctx: &impl C) and async fn shutdown(&self, ctx: &impl C), ctx being
+        // optional
+        if name == "link" || name == "shutdown" {
+            if sig.asyncness.is_none() || sig.receiver().is_some_and(|receiver| receiver.mutability.is_some()) {
+                return Err(syn::Error::new(sig.span(), format!("{} is an async fn taking &self", name)));
+            }
+            let call = match sig.inputs.len() {
+                1 => quote!(self.#name()),
+                2 => quote!(self.#name(ctx)),
+                _ => return Err(syn::Error::new(sig.span(), format!("{} takes &self and optionally the context", name))),
+            };
+            if name == "link" {
+                link_fn = Some(call);
+            } else {
+                shutdown_fn = Some(call);
+            }
+            continue;
+        }
+
+        // fn name(&self, ctx: &impl C, message: M) -> ..., ctx being optional
+        let args: Vec<_> = sig.inputs.iter().filter_map(|arg| match arg {
+            FnArg::Typed(arg) => Some(arg),
+            FnArg::Receiver(_) => None,
+        }).collect();
+        let message_arg = match args.as_slice() {
+            [message] | [_, message] => message,
+            _ => return Err(syn::Error::new(sig.span(), "Handler methods take &self, optionally the context, and the message")),
+        };
+        // a method taking &mut self makes this a serial handler
+        is_serial |= sig.receiver().is_some_and(|receiver| receiver.mutability.is_some());
+        let (message, takes_arc) = get_message_type(&message_arg.ty);
+        // a method taking Arc<M> is only right for pt_shared messages. The message is converted so
+        // the call compiles either way and the assertion gives the error.
+        let (message_expr, check) = if takes_arc {
+            let error = format!("{} takes an Arc of {}, which is only for #[pt_shared] messages", name, quote!(#message).to_string().replace(' ', ""));
+            let check = quote_spanned!(message_arg.ty.span()=> const { assert!(<#message as ::message_structs::Message>::IS_SHARED, #error) };);
+            (quote!(::std::convert::Into::into(message)), check)
+        } else {
+            (quote!(message), quote!())
+        };
+        let call_args = if args.len() == 2 { quote!(ctx, #message_expr) } else { quote!(#message_expr) };
+        let call = if sig.asyncness.is_some() {
+            quote!(::futures::FutureExt::boxed_local(self.#name(#call_args)))
+        } else {
+            quote!(self.#name(#call_args))
+        };
+
+        calls.push(quote!(#check #call));
+        handled_messages.push(message);
+    }
+
+    let handle_impls = handled_messages.iter().zip(calls).map(|(message, call)| if is_serial {
+        quote!(
+            impl #impl_generics ::handler_structs::HandleMut<#message> for #self_ty #where_clause {
+                #[allow(unused_variables)]
+                fn handle_mut<'a>(&'a mut self, ctx: &'a impl C, message: <#message as ::message_structs::Message>::HandlerMessage) -> <#message as ::message_structs::Message>::HandlerResponse<'a> {
+                    #call
+                }
+            }
+        )
+    } else {
+        quote!(
+            impl #impl_generics ::handler_structs::Handle<#message> for #self_ty #where_clause {
+                #[allow(unused_variables)]
+                fn handle<'a>(&'a self, ctx: &'a impl C, message: <#message as ::message_structs::Message>::HandlerMessage) -> <#message as ::message_structs::Message>::HandlerResponse<'a> {
+                    #call
+                }
+            }
+        )
+    });
+
+    let init_fn = init_fn.unwrap_or_else(|| quote!(<#self_ty as ::std::default::Default>::default()));
+    // Self or a Result
+    let init_result: Type = match init_output {
+        Some(syn::ReturnType::Type(_, ty)) => *ty,
+        Some(syn::ReturnType::Default) => return Err(syn::Error::new(self_ty.span(), "init returns Self or a Result")),
+        None => parse_quote!(Self),
+    };
+    let has_link = link_fn.is_some();
+    let has_shutdown = shutdown_fn.is_some();
+    let decl = HandlerDecl { handled_messages: handled_messages.clone(), init_requests, init_config, is_serial, has_link, has_shutdown };
+    let handler_impl = make_handler_impl(&self_ty, &ident, &type_args, &generics, decl);
+
+    let link_impl = link_fn.map(|call| quote!(
+        impl #impl_generics ::handler_structs::HandlerLink for #self_ty #where_clause {
+            #[allow(unused_variables)]
+            fn link<'a>(&'a self, ctx: &'a impl C) -> ::futures::future::LocalBoxFuture<'a, ()> {
+                ::futures::FutureExt::boxed_local(#call)
+            }
+        }
+    ));
+
+    let shutdown_impl = shutdown_fn.map(|call| quote!(
+        impl #impl_generics ::handler_structs::HandlerShutdown for #self_ty #where_clause {
+            #[allow(unused_variables)]
+            fn shutdown<'a>(&'a self, ctx: &'a impl C) -> ::futures::future::LocalBoxFuture<'a, ()> {
+                ::futures::FutureExt::boxed_local(#call)
+            }
+        }
+    ));
+
+    Ok(quote!(
+        #item
+
+        #handler_impl
+
+        impl #impl_generics ::handler_structs::HandlerInit for #self_ty #where_clause {
+            type InitResult = #init_result;
+
+            #[allow(unused_variables)]
+            async fn init<'a, Ctx: C + 'a>(ctx: &'a Self::InitCtx<'a, Ctx>, config: Self::InitConfig) -> Self::InitResult {
+                #init_fn
+            }
+        }
+
+        #link_impl
+
+        #shutdown_impl
+
+        #(#handle_impls)*
+    ))
+}
+
+// Implements a handler from its methods instead of attributes. Every method taking &self handles
+// the message that is its last parameter, taking an Arc of it for #[pt_shared] messages. Helper
+// methods are marked #[pt_skip] or go in a separate impl block. The
+// optional init function, which can be async and return a Result, gives the init requests, from
+// the CtxHandle bounds of its context parameter, and the config type. Handlers without one are
+// created with Default. If any method takes &mut self the handler is serial, as with
+// #[pt_serial]. Async link and shutdown methods make it a HandlerLink and a HandlerShutdown. An
+// impl of a generic handler for concrete types, e.g. impl EchoHandler<i32>, implements the handler
+// for just those type arguments.
+//
+// #[Agent]
+// impl ArithmeticHandler {
+//     async fn init(ctx: &impl CtxHandle<GetExampleInitValue>, config: Config) -> Self { ... }
+//     fn add1(&self, message: Add1) -> i32 { ... }
+//     async fn times3(&self, ctx: &impl C, message: Times3) -> i32 { ... }
+// }
+#[allow(non_snake_case)]
+#[proc_macro_attribute]
+pub fn Agent(attr: proc_macro::TokenStream, item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(Span::call_site(), "#[Agent] takes no arguments").to_compile_error().into();
+    }
+    let item = parse_macro_input!(item as ItemImpl);
+
+    match try_agent_macro(item) {
+        Ok(ts) => ts.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
 // The format of the Handler proc_macro is
 // #[derive(Handler)]
 // #[pt_handles(Add1, Times3)]
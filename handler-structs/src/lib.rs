@@ -1,3 +1,6 @@
+use std::{error::Error, future::Future};
+
+use futures::{FutureExt, future::LocalBoxFuture};
 use message_structs::{Message, MessageSpec};
 use message_list::C;
 use proc_macro2::Span;
@@ -8,6 +11,9 @@ pub struct HandlerSpec {
     pub handled_messages: Vec<&'static MessageSpec>,
     pub init_requests: Vec<&'static MessageSpec>,
     pub has_init_config: bool,
+    pub is_serial: bool,
+    pub has_link: bool,
+    pub has_shutdown: bool,
     pub span: Span,
 }
 
@@ -18,9 +24,61 @@ pub trait Handler {
     fn get_handler_spec(messages_in_context: &[&'static message_structs::MessageSpec]) -> HandlerSpec;
 }
 
-pub trait HandlerInit: Handler {
-    // Note that you cannot get a context proxy out of InitCtx, since not all handlers are initialised.
-    fn init<'a, Ctx: C + 'a>(ctx: &Self::InitCtx<'a, Ctx>, config: Self::InitConfig) -> Self;
+pub trait HandlerInit: Handler + Sized {
+    // InitCtx can only handle the messages in #[pt_init(...)], since not all handlers are initialised.
+    // Other messages can be posted or sent through InitCtx::proxy, they are queued until the context
+    // runs. Requests through the proxy fail with HandleError::NotRunning until then, as init can't
+    // wait for their responses.
+    // Implement with async fn. Handlers that don't depend on each other are initialised concurrently.
+    // Self, or Result<Self, E> if the handler can fail to start
+    type InitResult: IntoInitResult<Self>;
+
+    fn init<'a, Ctx: C + 'a>(ctx: &'a Self::InitCtx<'a, Ctx>, config: Self::InitConfig) -> impl Future<Output = Self::InitResult>;
+}
+
+// What HandlerInit::init returns, the handler or a Result with the reason it couldn't start.
+pub trait IntoInitResult<H> {
+    fn into_init_result(self) -> Result<H, Box<dyn Error>>;
+}
+
+impl<H: Handler> IntoInitResult<H> for H {
+    fn into_init_result(self) -> Result<H, Box<dyn Error>> {
+        Ok(self)
+    }
+}
+
+impl<H: Handler, E: Error + 'static> IntoInitResult<H> for Result<H, E> {
+    fn into_init_result(self) -> Result<H, Box<dyn Error>> {
+        self.map_err(|e| Box::new(e) as Box<dyn Error>)
+    }
+}
+
+// Implemented by handlers marked #[pt_link]. Once every handler has been initialised, link is
+// called on each of them in init order, before the context runs. Unlike init it gets the whole
+// context, so handlers that need each other can exchange requests, and it can give out proxies.
+// Messages sent through a proxy wait in the queue until the context runs, so requests through it
+// fail with HandleError::NotRunning here.
+// Like HandlerShutdown, handlers without the attribute get the default, which does nothing.
+pub trait HandlerLink: Handler {
+    fn link<'a>(&'a self, _ctx: &'a impl C) -> LocalBoxFuture<'a, ()> {
+        async {}.boxed_local()
+    }
+}
+
+// Implemented by handlers marked #[pt_shutdown]. When the context stops running, after quit(),
+// shutdown is called on each of them in reverse of the init order, so the handlers a handler needed
+// during init are still there. Messages can be sent to the context passed in, but not through
+// proxies, as the queue is closed.
+// When a handler fails to initialise, the handlers that were initialised are shut down, and their
+// context only has those handlers. Messages that can go to any number of handlers skip the others,
+// optional requests get None, and other requests fail with HandleError::NotInitialised through
+// try_handle and panic through handle.
+// The derive macro implements it with the default, which does nothing, for handlers without the
+// attribute, so implementing it on one of those is a conflicting implementation error.
+pub trait HandlerShutdown: Handler {
+    fn shutdown<'a>(&'a self, _ctx: &'a impl C) -> LocalBoxFuture<'a, ()> {
+        async {}.boxed_local()
+    }
 }
 
 // hidden::DeclaredHandle is implemented on the Handler by the derive macro. The Handle trait is then implemented by the user.
@@ -33,5 +91,16 @@ pub mod hidden {
 }
 
 pub trait Handle<T: Message>: hidden::DeclaredHandle<T> {
-    fn handle<'a>(&'a self, ctx: &'a impl C, message: T) -> T::Response<'a>;
+    fn handle<'a>(&'a self, ctx: &'a impl C, message: T::HandlerMessage) -> T::HandlerResponse<'a>;
+}
+
+// Implemented instead of Handle by handlers marked #[pt_serial]. The context keeps a serial handler
+// behind a mailbox that passes it one message at a time, so it gets &mut self, and other handlers
+// keep running while it waits. Only async messages can be sent to a serial handler.
+//
+// The handler is locked until the response is ready, so it mustn't wait for a message that comes
+// back to it, directly or through other handlers. The context panics if it does, except for
+// messages sent through a proxy, which deadlock. The same goes for its link and shutdown hooks.
+pub trait HandleMut<T: Message>: hidden::DeclaredHandle<T> {
+    fn handle_mut<'a>(&'a mut self, ctx: &'a impl C, message: T::HandlerMessage) -> T::HandlerResponse<'a>;
 }
@@ -1,22 +1,79 @@
-use example_handlers::Config;
 use handler_list::context_type;
-use smol::{LocalExecutor, future};
+use smol::{LocalExecutor, future, stream::StreamExt};
 use message_list::C;
-use std::thread;
+use context_structs::{serialized::{Json, Binary, SerializedMessage}, config::ConfigLoader};
+use std::{thread, time::Duration};
 
 context_type!();
 
+const _: () = context_structs::assert_can_handle::<Context, example_messages::Add2>();
+
 fn main() {
-    let config = ContextConfig {
-        arithmetic: Config {hello: false}
+    if std::env::args().any(|arg| arg == "--message-catalog") {
+        println!("{}", Context::message_catalog());
+        return;
+    }
+
+    // e.g. PT_ARITHMETIC__HELLO=true or --arithmetic.hello override config.toml
+    let config: ContextConfig = match ConfigLoader::new()
+        .optional_file("config.toml")
+        .env("PT")
+        .args(std::env::args().skip(1))
+        .load()
+        .and_then(|table| table.get())
+    {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid config: {}", e);
+            std::process::exit(1);
+        },
+    };
+    let context = match future::block_on(Context::new(config)) {
+        Ok(context) => context,
+        Err(e) => {
+            eprintln!("Failed to start: {}", e);
+            std::process::exit(1);
+        },
     };
-    let context = Context::new(config);
     let proxy = context.proxy();
 
     let thread = thread::spawn(move || {
         println!("Hello, world! {}", proxy.handle(example_messages::Add2{ x: 1 }));
+        println!("Echo {} {}", proxy.handle(example_messages::Echo{ value: 7 }), proxy.handle(example_messages::Echo{ value: "seven".to_string() }));
         future::block_on(proxy.handle(example_messages::NoResponse{ x: 102 }));
+        if let Err(e) = future::block_on(proxy.handle_with_timeout(example_messages::NoResponse{ x: 103 }, Duration::from_millis(100))) {
+            println!("NoResponse 103: {}", e);
+        }
+
+        let len = future::block_on(proxy.handle(example_messages::EditCommand::Insert{ at: 0, text: "hello world".to_string() }));
+        println!("Document length {}", len);
+        proxy.handle(example_messages::MoveCursor::End);
+        proxy.handle(example_messages::MoveCursor::Left(6));
+        println!("Document length {}", future::block_on(proxy.handle(example_messages::EditCommand::Delete{ at: 5, len: 6 })));
+        future::block_on(proxy.handle(example_messages::EditCommand::Insert{ at: 5, text: " hello".to_string() }));
+        let matches: Vec<usize> = future::block_on(proxy.handle(example_messages::FindInDocument{ pattern: "llo".to_string() }).collect());
+        println!("Found llo at {:?}", matches);
+        println!("Memory usage {}", proxy.handle(example_messages::MemoryUsage{}));
+        println!("Spell check {:?}", proxy.handle(example_messages::SpellCheck{ word: "helo".to_string() }));
+        for path in ["notes.txt", "sum.calc", "image.png"] {
+            println!("File type of {} is {:?}", path, proxy.handle(example_messages::FileType{ path: path.to_string() }));
+        }
+
+        let request = Context::encode_message::<Json, _>(&example_messages::Add2{ x: 5 }).unwrap();
+        println!("Serialized request {}", String::from_utf8(request.to_bytes().unwrap()).unwrap());
+        let response = future::block_on(Context::handle_serialized(&*proxy, &request)).unwrap();
+        println!("Serialized response {}", String::from_utf8(response.to_bytes().unwrap()).unwrap());
+
+        let request = Context::encode_message::<Binary, _>(&example_messages::Times3{ x: 5 }).unwrap();
+        let request = SerializedMessage::<Binary>::from_bytes(&request.to_bytes().unwrap()).unwrap();
+        let response = future::block_on(Context::handle_serialized(&*proxy, &request)).unwrap();
+        println!("Binary response {}", Context::decode_response::<Binary, example_messages::Times3>(&response).unwrap());
         proxy.quit();
+
+        match proxy.try_handle(example_messages::Add2{ x: 1 }) {
+            Ok(response) => println!("Unexpected response after quit {}", response),
+            Err(e) => println!("After quit: {}", e),
+        }
     });
 
     let executor = LocalExecutor::new();
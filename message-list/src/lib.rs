@@ -1,15 +1,11 @@
 use context_proc_macros::message_list;
-use application_messages::*;
 
+// crate::* is every message registered by the crate's build script. Generic messages have to be
+// listed with their type arguments.
 message_list!{[
-    example_messages::Add1,
-    example_messages::Add2,
-    example_messages::Times3,
-    example_messages::GetExampleInitValue,
-    example_messages::NoResponse,
+    example_messages::*,
+    example_messages::Echo<i32>,
+    example_messages::Echo<String>,
 
-    OpenWindow,
-    CloseWindow,
-    ExitProgram,
-    KeyPress,
+    application_messages::*,
 ]}
@@ -1,6 +1,6 @@
 use proc_macro2::TokenStream;
-use syn::{parse_macro_input, DeriveInput, Attribute, Generics};
-use quote::quote;
+use syn::{parse_macro_input, DeriveInput, Attribute, GenericParam, Data, Fields, Expr, Lit, Meta};
+use quote::{quote, ToTokens};
 
 fn get_attribute<'a>(attrs: &'a [Attribute], to_find: &str) -> Option<&'a Attribute> {
     for attr in attrs {
@@ -11,7 +11,7 @@ fn get_attribute<'a>(attrs: &'a [Attribute], to_find: &str) -> Option<&'a Attrib
             }
         }
     }
-    return None;
+    None
 }
 
 fn has_attribute(attrs: &[Attribute], to_find: &str) -> bool {
@@ -22,10 +22,22 @@ fn is_async(attrs: &[Attribute]) -> bool {
     !has_attribute(attrs, "pt_sync")
 }
 
-fn assert_not_generic(ast: &DeriveInput) {
-    if ast.generics != Generics::default() {
-        panic!("Generic messages are not supported");
+fn is_available_during_init(attrs: &[Attribute]) -> bool {
+    !has_attribute(attrs, "pt_not_during_init")
+}
+
+fn is_serde(attrs: &[Attribute]) -> bool {
+    has_attribute(attrs, "pt_serde")
+}
+
+// Each instantiation of a generic message is a separate message, so only type parameters are supported.
+fn check_generics(ast: &DeriveInput) -> syn::Result<()> {
+    for param in &ast.generics.params {
+        if !matches!(param, GenericParam::Type(_)) {
+            return Err(syn::Error::new_spanned(param, "Messages can only be generic over types"));
+        }
     }
+    Ok(())
 }
 
 fn parse_pt_response(attr: &Attribute) -> syn::Result<syn::Type> {
@@ -37,45 +49,371 @@ fn get_response_type(attrs: &[Attribute]) -> Option<syn::Result<syn::Type>> {
     get_attribute(attrs, "pt_response").map(parse_pt_response)
 }
 
+fn get_stream_type(attrs: &[Attribute]) -> Option<syn::Result<syn::Type>> {
+    get_attribute(attrs, "pt_stream").map(parse_pt_response)
+}
+
+fn get_priority(attrs: &[Attribute]) -> syn::Result<TokenStream> {
+    let Some(attr) = get_attribute(attrs, "pt_priority") else {
+        return Ok(quote!(::message_structs::Priority::Normal));
+    };
+    let priority: syn::Ident = attr.parse_args()?;
+    match priority.to_string().as_str() {
+        "high" => Ok(quote!(::message_structs::Priority::High)),
+        "normal" => Ok(quote!(::message_structs::Priority::Normal)),
+        "low" => Ok(quote!(::message_structs::Priority::Low)),
+        _ => Err(syn::Error::new_spanned(priority, "Expected high, normal or low")),
+    }
+}
+
+fn get_timeout(attrs: &[Attribute]) -> syn::Result<TokenStream> {
+    let Some(attr) = get_attribute(attrs, "pt_timeout") else {
+        return Ok(quote!(::std::option::Option::None));
+    };
+    let ms: syn::LitInt = attr.parse_args()?;
+    let ms: u64 = ms.base10_parse()?;
+    Ok(quote!(::std::option::Option::Some(::std::time::Duration::from_millis(#ms))))
+}
+
+// pt_collect(Vec) and pt_collect(Sum) are the built in reducers, anything else is a path to a type
+// implementing Collect.
+fn get_collect(attrs: &[Attribute]) -> syn::Result<Option<TokenStream>> {
+    let Some(attr) = get_attribute(attrs, "pt_collect") else {
+        return Ok(None);
+    };
+    if !has_attribute(attrs, "pt_response") {
+        return Err(syn::Error::new_spanned(attr, "pt_collect messages need a pt_response"));
+    }
+    let reducer: syn::Path = attr.parse_args()?;
+    Ok(Some(if reducer.is_ident("Vec") {
+        quote!(::message_structs::collect::Vec)
+    } else if reducer.is_ident("Sum") {
+        quote!(::message_structs::collect::Sum)
+    } else {
+        quote!(#reducer)
+    }))
+}
+
+fn check_first_match(attrs: &[Attribute]) -> syn::Result<()> {
+    let Some(attr) = get_attribute(attrs, "pt_first_match") else {
+        return Ok(());
+    };
+    if !has_attribute(attrs, "pt_response") {
+        return Err(syn::Error::new_spanned(attr, "pt_first_match messages need a pt_response"));
+    }
+    if let Some(collect) = get_attribute(attrs, "pt_collect") {
+        return Err(syn::Error::new_spanned(collect, "pt_first_match messages can't be pt_collect"));
+    }
+    Ok(())
+}
+
+// Collect and first match messages can already have no handlers
+fn check_optional(attrs: &[Attribute]) -> syn::Result<()> {
+    let Some(attr) = get_attribute(attrs, "pt_optional") else {
+        return Ok(());
+    };
+    if !has_attribute(attrs, "pt_response") {
+        return Err(syn::Error::new_spanned(attr, "pt_optional messages need a pt_response"));
+    }
+    for other in ["pt_collect", "pt_first_match"] {
+        if let Some(other_attr) = get_attribute(attrs, other) {
+            return Err(syn::Error::new_spanned(other_attr, format!("pt_optional messages can't be {}", other)));
+        }
+    }
+    Ok(())
+}
+
+// The handler of a stream message returns a stream, so it can't also have a single response, be
+// handled synchronously or be serialized as one response.
+fn check_stream(attrs: &[Attribute]) -> syn::Result<()> {
+    if !has_attribute(attrs, "pt_stream") {
+        return Ok(());
+    }
+    for other in ["pt_response", "pt_sync", "pt_serde", "pt_collect", "pt_first_match", "pt_optional"] {
+        if let Some(attr) = get_attribute(attrs, other) {
+            return Err(syn::Error::new_spanned(attr, format!("pt_stream messages can't be {}", other)));
+        }
+    }
+    Ok(())
+}
+
+// Token streams print with spaces between every token, e.g. "Vec < i32 >". Only keep the spaces
+// that are needed to separate words.
+fn tokens_to_string(tokens: &impl ToTokens) -> String {
+    let spaced = tokens.to_token_stream().to_string();
+    let chars: Vec<char> = spaced.chars().collect();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '\'' || c == '"';
+    let mut rtn = String::with_capacity(spaced.len());
+    for (i, c) in chars.iter().enumerate() {
+        if *c == ' ' {
+            let keep = i > 0 && i + 1 < chars.len() && ((is_word(chars[i - 1]) && is_word(chars[i + 1])) || chars[i - 1] == ',');
+            if !keep {
+                continue;
+            }
+        }
+        rtn.push(*c);
+    }
+    rtn
+}
+
+// Doc comments are #[doc = "..."] attributes, one per line.
+fn get_docs(attrs: &[Attribute]) -> String {
+    let lines: Vec<String> = attrs.iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(nv) if nv.path.is_ident("doc") => match &nv.value {
+                Expr::Lit(expr) => match &expr.lit {
+                    Lit::Str(s) => Some(s.value()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|line| line.strip_prefix(' ').map(str::to_owned).unwrap_or(line))
+        .collect();
+    lines.join("\n")
+}
+
+fn get_other_attributes(attrs: &[Attribute]) -> Vec<String> {
+    attrs.iter()
+        .filter(|attr| !attr.path().is_ident("doc"))
+        .map(|attr| tokens_to_string(&attr.meta))
+        .collect()
+}
+
+// A generic message's spec is built for each instantiation, so its slices are leaked rather than
+// being static
+fn make_slice(items: Vec<TokenStream>, is_generic: bool) -> TokenStream {
+    if is_generic {
+        quote!(::std::boxed::Box::leak(::std::vec![#(#items),*].into_boxed_slice()))
+    } else {
+        quote!(&[#(#items),*])
+    }
+}
+
+// The type as written in the message definition. For generic messages the type parameters are
+// replaced with the type arguments, type_params and type_args are in scope where the spec is built.
+fn make_type_string(ty: String, is_generic: bool) -> TokenStream {
+    if is_generic {
+        quote!(::message_structs::hidden::substitute_type_params(#ty, type_params, type_args))
+    } else {
+        quote!(#ty)
+    }
+}
+
+fn make_field_specs(fields: &Fields, is_generic: bool) -> TokenStream {
+    let field_specs = fields.iter().map(|field| {
+        let name = match &field.ident {
+            Some(ident) => {
+                let name = ident.to_string();
+                quote!(::std::option::Option::Some(#name))
+            },
+            None => quote!(::std::option::Option::None),
+        };
+        let ty = make_type_string(tokens_to_string(&field.ty), is_generic);
+        let docs = get_docs(&field.attrs);
+        quote!(::message_structs::FieldSpec {
+            name: #name,
+            ty: #ty,
+            docs: #docs,
+        })
+    });
+    make_slice(field_specs.collect(), is_generic)
+}
+
 fn try_message_macro(ast: DeriveInput) -> syn::Result<TokenStream> {
+    check_generics(&ast)?;
+    check_stream(&ast.attrs)?;
+    check_first_match(&ast.attrs)?;
+    check_optional(&ast.attrs)?;
+
     let is_async = is_async(&ast.attrs);
-    let ident = ast.ident;
+    let available_during_init = is_available_during_init(&ast.attrs);
+    let is_serde = is_serde(&ast.attrs);
+    let ident = &ast.ident;
+    // the type arguments of a generic message go in its name, so they have to implement TypePath
+    let mut generics = ast.generics.clone();
+    for param in ast.generics.type_params() {
+        let ident = &param.ident;
+        generics.make_where_clause().predicates.push(syn::parse_quote!(#ident: ::message_structs::TypePath));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let is_stream = has_attribute(&ast.attrs, "pt_stream");
+    let is_collect = has_attribute(&ast.attrs, "pt_collect");
+    let is_first_match = has_attribute(&ast.attrs, "pt_first_match");
+    let is_optional = has_attribute(&ast.attrs, "pt_optional");
+    let is_shared = has_attribute(&ast.attrs, "pt_shared");
+    let priority = get_priority(&ast.attrs)?;
+    let timeout = get_timeout(&ast.attrs)?;
 
-    let (response_type, has_response) = match get_response_type(&ast.attrs) {
+    // a stream message has one handler, like a request, but responds with a stream of its items.
+    // The handlers of a first match message respond with None to pass the message on.
+    let (response_type, has_response) = match get_response_type(&ast.attrs).or_else(|| get_stream_type(&ast.attrs)) {
+        Some(Ok(t)) if is_first_match => (quote! {::std::option::Option<#t>}, true),
         Some(Ok(t)) => (quote! {#t}, true),
         Some(Err(e)) => return Err(e),
         None => (quote!{()}, false),
     };
 
-    let wrapped_response_type = if is_async {
-        quote!{::futures::future::LocalBoxFuture<'a, #response_type>}
+    // for a collect message every handler responds with a pt_response, which are then combined
+    let collect = get_collect(&ast.attrs)?;
+    // an optional message responds with None if no handler handles it
+    let unwrapped_response_type = match &collect {
+        Some(collect) => quote!(<#collect as ::message_structs::Collect<#response_type>>::Output),
+        None if is_optional => quote!(::std::option::Option<#response_type>),
+        None => response_type.clone(),
+    };
+
+    let wrap = |ty: &TokenStream| if is_stream {
+        quote!{::futures::stream::LocalBoxStream<'a, #ty>}
+    } else if is_async {
+        quote!{::futures::future::LocalBoxFuture<'a, #ty>}
+    } else {
+        ty.clone()
+    };
+    let wrapped_response_type = wrap(&unwrapped_response_type);
+    let handler_response_type = wrap(&response_type);
+    let wrapped_type = wrap(&quote!(R));
+
+    let handler_message_type = if is_shared {
+        quote!(::std::sync::Arc<Self>)
     } else {
-        response_type.clone()
+        quote!(Self)
+    };
+
+    let is_generic = !ast.generics.params.is_empty();
+    let data = match &ast.data {
+        Data::Struct(data) => {
+            let fields = make_field_specs(&data.fields, is_generic);
+            quote!(::message_structs::MessageData::Struct(#fields))
+        },
+        Data::Enum(data) => {
+            let variants = data.variants.iter().map(|variant| {
+                let name = variant.ident.to_string();
+                let fields = make_field_specs(&variant.fields, is_generic);
+                let docs = get_docs(&variant.attrs);
+                quote!(::message_structs::VariantSpec {
+                    name: #name,
+                    fields: #fields,
+                    docs: #docs,
+                })
+            });
+            let variants = make_slice(variants.collect(), is_generic);
+            quote!(::message_structs::MessageData::Enum(#variants))
+        },
+        Data::Union(data) => {
+            return Err(syn::Error::new_spanned(data.union_token, "Unions can't be messages"));
+        },
+    };
+    // what the sender gets, written the way the reducers of pt_collect(Vec) and pt_collect(Sum) would
+    // be written by hand
+    let response_type_string = match get_response_type(&ast.attrs).or_else(|| get_stream_type(&ast.attrs)) {
+        Some(Ok(t)) => {
+            let t = match get_attribute(&ast.attrs, "pt_collect").map(|attr| attr.parse_args::<syn::Path>()).transpose()? {
+                Some(reducer) if reducer.is_ident("Vec") => format!("Vec<{}>", tokens_to_string(&t)),
+                Some(reducer) if reducer.is_ident("Sum") => tokens_to_string(&t),
+                Some(_) => tokens_to_string(&unwrapped_response_type),
+                None if is_first_match || is_optional => format!("Option<{}>", tokens_to_string(&t)),
+                None => tokens_to_string(&t),
+            };
+            let t = make_type_string(t, is_generic);
+            quote!(::std::option::Option::Some(#t))
+        },
+        _ => quote!(::std::option::Option::None),
+    };
+    let docs = get_docs(&ast.attrs);
+    let attributes = get_other_attributes(&ast.attrs);
+
+    let spec_fields = quote!(
+        is_async: #is_async,
+        is_stream: #is_stream,
+        is_collect: #is_collect,
+        is_first_match: #is_first_match,
+        is_optional: #is_optional,
+        is_shared: #is_shared,
+        has_response: #has_response,
+        available_during_init: #available_during_init,
+        is_serde: #is_serde,
+        priority: #priority,
+        timeout: #timeout,
+        data: #data,
+        response_type: #response_type_string,
+        docs: #docs,
+        attributes: &[#(#attributes),*],
+    );
+
+    let get_message_spec_body = if !is_generic {
+        quote!(
+            const NAME: &str = concat!("::", module_path!(), "::", stringify!(#ident));
+            static s: ::message_structs::MessageSpec = ::message_structs::MessageSpec {
+                id: ::message_structs::message_id(NAME),
+                name: NAME,
+                #spec_fields
+            };
+            &s
+        )
+    } else {
+        let type_params = ast.generics.type_params().map(|param| &param.ident).collect::<Vec<_>>();
+        quote!(
+            let type_params: &[&str] = &[#( stringify!(#type_params) ),*];
+            let type_args: &[::std::string::String] = &[#( <#type_params as ::message_structs::TypePath>::type_path() ),*];
+            let name = format!("{}<{}>", concat!("::", module_path!(), "::", stringify!(#ident)), type_args.join(", "));
+            ::message_structs::hidden::generic_message_spec(name, |name| ::message_structs::MessageSpec {
+                id: ::message_structs::message_id(name),
+                name,
+                #spec_fields
+            })
+        )
+    };
+
+    // the bounds are on the impl so that generic messages only need their type arguments to be
+    // serializable when they are instantiated.
+    let serde_message_impl = if is_serde {
+        let mut where_clause = generics.where_clause.clone().unwrap_or_else(|| syn::parse_quote!(where));
+        where_clause.predicates.push(syn::parse_quote!(Self: ::message_structs::hidden::serde::Serialize + ::message_structs::hidden::serde::de::DeserializeOwned));
+        where_clause.predicates.push(syn::parse_quote!(#unwrapped_response_type: ::message_structs::hidden::serde::Serialize + ::message_structs::hidden::serde::de::DeserializeOwned));
+        quote!(
+            impl #impl_generics ::message_structs::SerdeMessage for #ident #ty_generics #where_clause {}
+        )
+    } else {
+        quote!()
+    };
+
+    let collect_message_impl = match &collect {
+        Some(collect) => quote!(
+            impl #impl_generics ::message_structs::CollectMessage for #ident #ty_generics #where_clause {
+                type Item = #response_type;
+                type Collector = #collect;
+            }
+        ),
+        None => quote!(),
     };
 
     Ok(quote!(
-        impl ::message_structs::Message for #ident {
+        #serde_message_impl
+        #collect_message_impl
+
+        impl #impl_generics ::message_structs::Message for #ident #ty_generics #where_clause {
             type Response<'a> = #wrapped_response_type;
-            type UnwrappedResponse = #response_type;
+            type UnwrappedResponse = #unwrapped_response_type;
+            type HandlerResponse<'a> = #handler_response_type;
+            type HandlerMessage = #handler_message_type;
+            type Wrapped<'a, R> = #wrapped_type;
+            const IS_SHARED: bool = #is_shared;
+            const AVAILABLE_DURING_INIT: bool = #available_during_init;
 
             fn get_message_spec() -> &'static ::message_structs::MessageSpec {
-                static s: ::message_structs::MessageSpec = ::message_structs::MessageSpec {
-                    name: concat!("::", module_path!(), "::", stringify!(#ident)),
-                    is_async: #is_async,
-                    has_response: #has_response,
-                };
-                &s
+                #get_message_spec_body
             }
         }
     ))
 }
 
 
-#[proc_macro_derive(Message, attributes(pt_sync, pt_response))]
+#[proc_macro_derive(Message, attributes(pt_sync, pt_response, pt_stream, pt_collect, pt_first_match, pt_optional, pt_shared, pt_priority, pt_timeout, pt_not_during_init, pt_serde))]
 pub fn message_macro(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast = parse_macro_input!(item as DeriveInput);
-    
-    assert_not_generic(&ast);
 
     match try_message_macro(ast) {
         Ok(ts) => ts.into(),
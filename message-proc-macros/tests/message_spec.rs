@@ -0,0 +1,86 @@
+use message_proc_macros::Message;
+use message_structs::{Message, message_id};
+
+#[derive(Message)]
+#[pt_response(T)]
+#[pt_sync]
+pub struct Wrap<T> {
+    pub value: T,
+}
+
+#[derive(Message)]
+#[pt_sync]
+pub struct Plain {}
+
+#[test]
+fn message_id_is_fnv_1a() {
+    // ids go over the wire, so they must never change
+    assert_eq!(message_id(""), 0xcbf29ce484222325);
+    assert_eq!(message_id("a"), 0xaf63dc4c8601ec8c);
+    assert_eq!(message_id("foobar"), 0x85944171f73967e8);
+}
+
+#[test]
+fn message_id_hashes_the_name() {
+    let spec = Plain::get_message_spec();
+    assert_eq!(spec.name, "::message_spec::Plain");
+    assert_eq!(spec.id, message_id("::message_spec::Plain"));
+}
+
+#[test]
+fn generic_messages_are_named_with_type_paths() {
+    let spec = Wrap::<String>::get_message_spec();
+    assert_eq!(spec.name, "::message_spec::Wrap<::std::string::String>");
+    assert_eq!(spec.id, message_id("::message_spec::Wrap<::std::string::String>"));
+
+    let spec = Wrap::<Vec<(i32, Option<u8>)>>::get_message_spec();
+    assert_eq!(spec.name, "::message_spec::Wrap<::std::vec::Vec<(i32, ::std::option::Option<u8>)>>");
+}
+
+#[test]
+fn each_instantiation_has_its_own_spec() {
+    let a = Wrap::<i32>::get_message_spec();
+    let b = Wrap::<i64>::get_message_spec();
+    assert_ne!(a.id, b.id);
+    assert!(std::ptr::eq(a, Wrap::<i32>::get_message_spec()));
+}
+
+#[test]
+fn generic_specs_describe_the_type_arguments() {
+    let spec = Wrap::<Vec<String>>::get_message_spec();
+    let message_structs::MessageData::Struct(fields) = &spec.data else { panic!("Wrap is a struct") };
+    assert_eq!(fields[0].name, Some("value"));
+    assert_eq!(fields[0].ty, "::std::vec::Vec<::std::string::String>");
+    assert_eq!(spec.response_type, Some("::std::vec::Vec<::std::string::String>"));
+}
+
+#[test]
+fn type_params_in_paths_are_left_alone() {
+    let ty = message_structs::hidden::substitute_type_params("my::T<T>", &["T"], &["u8".to_owned()]);
+    assert_eq!(ty, "my::T<u8>");
+}
+
+#[derive(Message)]
+#[pt_response(u32)]
+#[pt_collect(Vec)]
+#[pt_sync]
+pub struct CollectVec {}
+
+#[derive(Message)]
+#[pt_response(u32)]
+#[pt_collect(Sum)]
+#[pt_sync]
+pub struct CollectSum {}
+
+#[derive(Message)]
+#[pt_response(u32)]
+#[pt_optional]
+#[pt_sync]
+pub struct Optional {}
+
+#[test]
+fn response_type_is_what_the_sender_gets() {
+    assert_eq!(CollectVec::get_message_spec().response_type, Some("Vec<u32>"));
+    assert_eq!(CollectSum::get_message_spec().response_type, Some("u32"));
+    assert_eq!(Optional::get_message_spec().response_type, Some("Option<u32>"));
+}
@@ -0,0 +1,339 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use quote::ToTokens;
+use syn::{Attribute, Item, Visibility, Meta, punctuated::Punctuated, Token};
+
+// Lets message_list! include every message in a crate with crate_name::*. Call register_messages()
+// from the build.rs of the messages crate, and include the registry in its lib.rs with
+// include!(concat!(env!("OUT_DIR"), "/message_registry.rs"));
+//
+// The registry is a pt_registered_messages! macro that lists the paths of the messages, relative
+// to the crate root. Only messages that message_list! can name are registered, i.e. public,
+// non-generic items deriving Message in public modules. Generic messages have to be listed with
+// their type arguments.
+//
+// The messages are found by reading the crate's source, as message_list! needs them while the
+// crate using it is compiled. #[path = "..."] modules are followed and #[cfg(...)] is evaluated
+// with the crate's features and target. Messages made by macros or re-exported with pub use can't
+// be seen, the build warns about those and they have to be listed by path. Anything that can't be
+// read fails the build rather than being left out.
+pub fn register_messages() {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let src_dir = manifest_dir.join("src");
+    println!("cargo:rerun-if-changed={}", src_dir.display());
+
+    let env = |name: &str| std::env::var(name).ok();
+    let scan = Scan::crate_root(&src_dir, &env);
+    for file in &scan.module_files {
+        println!("cargo:rerun-if-changed={}", file.display());
+    }
+    for warning in &scan.warnings {
+        println!("cargo:warning={}", warning);
+    }
+
+    let registry = format!(
+        "// Generated by message_registry::register_messages, the messages in this crate for message_list!\n\
+        #[doc(hidden)]\n\
+        #[macro_export]\n\
+        macro_rules! pt_registered_messages {{\n    \
+            ($($callback:ident)::+ {{ $($args:tt)* }}) => {{\n        \
+                $($callback)::+! {{ $($args)* [{}] }}\n    \
+            }};\n\
+        }}\n",
+        scan.messages.join(", ")
+    );
+    fs::write(out_dir.join("message_registry.rs"), registry).unwrap();
+}
+
+fn parse_file(path: &Path) -> syn::File {
+    let source = fs::read_to_string(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+    syn::parse_file(&source).unwrap_or_else(|e| panic!("Failed to parse {}: {}", path.display(), e))
+}
+
+fn derives_message(attrs: &[Attribute]) -> bool {
+    attrs.iter()
+        .filter(|attr| attr.path().is_ident("derive"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::List(list) => list.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated).ok(),
+            _ => None,
+        })
+        .flatten()
+        .any(|path| path.segments.last().is_some_and(|segment| segment.ident == "Message"))
+}
+
+fn is_registry_include(mac: &syn::Macro) -> bool {
+    mac.path.is_ident("include") && mac.tokens.to_string().contains("message_registry.rs")
+}
+
+fn location(file: &Path, module: &[String]) -> String {
+    if module.is_empty() {
+        file.display().to_string()
+    } else {
+        format!("{} ({})", file.display(), module.join("::"))
+    }
+}
+
+// The messages found in a crate's source. env looks up the build script's environment, which is
+// where cargo passes the features and target cfgs.
+struct Scan<'a> {
+    env: &'a dyn Fn(&str) -> Option<String>,
+    messages: Vec<String>,
+    warnings: Vec<String>,
+    // #[path] modules, which can be outside src, so the build has to rerun when they change too
+    module_files: Vec<PathBuf>,
+}
+
+impl<'a> Scan<'a> {
+    fn crate_root(src_dir: &Path, env: &'a dyn Fn(&str) -> Option<String>) -> Self {
+        let mut scan = Scan { env, messages: Vec::new(), warnings: Vec::new(), module_files: Vec::new() };
+        let lib = src_dir.join("lib.rs");
+        scan.find_messages(&parse_file(&lib).items, &lib, src_dir, &mut Vec::new());
+        scan
+    }
+
+    // Whether every #[cfg(...)] on an item holds for the crate being built. Build scripts get the
+    // features as CARGO_FEATURE_<NAME> and the target's cfgs as CARGO_CFG_<NAME>.
+    fn cfg_enabled(&self, attrs: &[Attribute], file: &Path) -> bool {
+        attrs.iter()
+            .filter(|attr| attr.path().is_ident("cfg"))
+            .all(|attr| {
+                let predicate: Meta = attr.parse_args()
+                    .unwrap_or_else(|e| panic!("Failed to parse a cfg in {}: {}", file.display(), e));
+                self.eval_cfg(&predicate, file)
+            })
+    }
+
+    fn eval_cfg(&self, predicate: &Meta, file: &Path) -> bool {
+        let env_name = |name: &str| name.to_uppercase().replace('-', "_");
+        let unsupported = || -> ! {
+            let predicate = predicate.to_token_stream().to_string();
+            panic!("Can't evaluate cfg({}) in {} to find its messages", predicate, file.display())
+        };
+
+        match predicate {
+            // the registry is shared by every build of the crate, so test only code is never included
+            Meta::Path(path) if path.is_ident("test") || path.is_ident("doc") => false,
+            Meta::Path(path) => match path.get_ident() {
+                Some(ident) => (self.env)(&format!("CARGO_CFG_{}", env_name(&ident.to_string()))).is_some(),
+                None => unsupported(),
+            },
+            Meta::NameValue(name_value) => {
+                let (Some(key), syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(value), .. })) = (name_value.path.get_ident(), &name_value.value) else {
+                    unsupported()
+                };
+                if key == "feature" {
+                    (self.env)(&format!("CARGO_FEATURE_{}", env_name(&value.value()))).is_some()
+                } else {
+                    // some cfgs have several values, e.g. target_family
+                    (self.env)(&format!("CARGO_CFG_{}", env_name(&key.to_string())))
+                        .is_some_and(|values| values.split(',').any(|v| v == value.value()))
+                }
+            },
+            Meta::List(list) => {
+                let predicates = list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                    .unwrap_or_else(|_| unsupported());
+                if list.path.is_ident("all") {
+                    predicates.iter().all(|p| self.eval_cfg(p, file))
+                } else if list.path.is_ident("any") {
+                    predicates.iter().any(|p| self.eval_cfg(p, file))
+                } else if list.path.is_ident("not") && predicates.len() == 1 {
+                    !self.eval_cfg(&predicates[0], file)
+                } else {
+                    unsupported()
+                }
+            },
+        }
+    }
+
+    // file is the file the items are in, dir is where the files of submodules of the current module are
+    fn find_messages(&mut self, items: &[Item], file: &Path, dir: &Path, module: &mut Vec<String>) {
+        for item in items {
+            let (attrs, vis, ident, generics) = match item {
+                Item::Struct(s) => (&s.attrs, &s.vis, &s.ident, &s.generics),
+                Item::Enum(e) => (&e.attrs, &e.vis, &e.ident, &e.generics),
+                Item::Mod(m) => {
+                    if !matches!(m.vis, Visibility::Public(_)) || !self.cfg_enabled(&m.attrs, file) {
+                        continue;
+                    }
+                    let name = m.ident.to_string();
+                    module.push(name.clone());
+                    match &m.content {
+                        Some((_, items)) => self.find_messages(items, file, &dir.join(&name), module),
+                        None => {
+                            let path_attr = m.attrs.iter().find(|attr| attr.path().is_ident("path"));
+                            let (module_file, module_dir) = match path_attr {
+                                // relative to the directory of the current file, the module's own
+                                // submodules are next to it
+                                Some(attr) => {
+                                    let Meta::NameValue(syn::MetaNameValue { value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(path), .. }), .. }) = &attr.meta else {
+                                        panic!("Can't read the #[path] of module {} in {}", module.join("::"), file.display());
+                                    };
+                                    let module_file = file.parent().unwrap().join(path.value());
+                                    let module_dir = module_file.parent().unwrap().to_owned();
+                                    self.module_files.push(module_file.clone());
+                                    (module_file, module_dir)
+                                },
+                                None => {
+                                    let module_file = [dir.join(format!("{}.rs", name)), dir.join(&name).join("mod.rs")]
+                                        .into_iter()
+                                        .find(|path| path.exists())
+                                        .unwrap_or_else(|| panic!("Can't find the file for module {} in {}", module.join("::"), file.display()));
+                                    (module_file, dir.join(&name))
+                                },
+                            };
+                            self.find_messages(&parse_file(&module_file).items, &module_file, &module_dir, module);
+                        },
+                    }
+                    module.pop();
+                    continue;
+                },
+                Item::Macro(m) if m.mac.path.is_ident("macro_rules") || is_registry_include(&m.mac) => continue,
+                Item::Macro(m) => {
+                    let name = m.mac.path.segments.iter().map(|s| s.ident.to_string()).collect::<Vec<_>>().join("::");
+                    self.warnings.push(format!("Messages made by {}! in {} aren't registered, list them in message_list! by path", name, location(file, module)));
+                    continue;
+                },
+                Item::Use(u) if matches!(u.vis, Visibility::Public(_)) && self.cfg_enabled(&u.attrs, file) => {
+                    self.warnings.push(format!("Messages re-exported by pub use in {} aren't registered, list them in message_list! by path", location(file, module)));
+                    continue;
+                },
+                _ => continue,
+            };
+
+            if derives_message(attrs) && matches!(vis, Visibility::Public(_)) && generics.params.is_empty() && self.cfg_enabled(attrs, file) {
+                let mut path = module.clone();
+                path.push(ident.to_string());
+                self.messages.push(path.join("::"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes files, as paths relative to a new crate dir, and scans its src with the given build
+    // script environment
+    fn scan(name: &str, files: &[(&str, &str)], env: &[(&str, &str)]) -> (Vec<String>, Vec<String>) {
+        let crate_dir = std::env::temp_dir().join(format!("message-registry-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&crate_dir);
+        for (path, source) in files {
+            let path = crate_dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        let env = |name: &str| env.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_string());
+        let scan = Scan::crate_root(&crate_dir.join("src"), &env);
+        fs::remove_dir_all(&crate_dir).unwrap();
+        (scan.messages, scan.warnings)
+    }
+
+    #[test]
+    fn cfgs_are_evaluated_with_the_features_and_target() {
+        let lib = r#"
+            #[derive(Message)] #[cfg(feature = "extra-messages")] pub struct Extra;
+            #[derive(Message)] #[cfg(feature = "missing")] pub struct Missing;
+            #[derive(Message)] #[cfg(unix)] pub struct Unix;
+            #[derive(Message)] #[cfg(target_os = "linux")] pub struct Linux;
+            #[derive(Message)] #[cfg(target_os = "windows")] pub struct Windows;
+            #[derive(Message)] #[cfg(target_family = "wasm")] pub struct Wasm;
+            #[derive(Message)] #[cfg(all(unix, feature = "extra-messages"))] pub struct All;
+            #[derive(Message)] #[cfg(all(unix, feature = "missing"))] pub struct NotAll;
+            #[derive(Message)] #[cfg(any(windows, target_os = "linux"))] pub struct Any;
+            #[derive(Message)] #[cfg(any())] pub struct NotAny;
+            #[derive(Message)] #[cfg(not(windows))] pub struct NotWindows;
+            #[derive(Message)] #[cfg(not(unix))] pub struct NotUnix;
+            #[derive(Message)] #[cfg(test)] pub struct Test;
+            #[derive(Message)] #[cfg(unix)] #[cfg(windows)] pub struct Both;
+            #[cfg(windows)] pub mod windows_messages { #[derive(Message)] pub struct InWindowsModule; }
+        "#;
+        let env = [
+            ("CARGO_FEATURE_EXTRA_MESSAGES", "1"),
+            ("CARGO_CFG_UNIX", ""),
+            ("CARGO_CFG_TARGET_OS", "linux"),
+            ("CARGO_CFG_TARGET_FAMILY", "unix,wasm"),
+        ];
+
+        let (messages, _) = scan("cfg", &[("src/lib.rs", lib)], &env);
+        assert_eq!(messages, ["Extra", "Unix", "Linux", "Wasm", "All", "Any", "NotWindows"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't evaluate cfg")]
+    fn unknown_cfg_predicates_fail() {
+        scan("unknown-cfg", &[("src/lib.rs", "#[cfg(maybe(unix))] #[derive(Message)] pub struct M;")], &[]);
+    }
+
+    #[test]
+    fn modules_are_followed() {
+        let files = [
+            ("src/lib.rs", r#"
+                pub mod inline { pub mod nested { #[derive(Message)] pub struct Inline; } }
+                pub mod file;
+                pub mod dir;
+                #[path = "../shared/moved.rs"] pub mod moved;
+                mod private;
+            "#),
+            ("src/file.rs", "pub mod sub; #[derive(Message)] pub struct InFile;"),
+            ("src/file/sub.rs", "#[derive(Message)] pub struct InSub;"),
+            ("src/dir/mod.rs", "#[derive(message_structs::Message)] pub struct InModRs;"),
+            // submodules of a #[path] module are next to its file
+            ("shared/moved.rs", "pub mod beside; #[derive(Message)] pub struct Moved;"),
+            ("shared/beside.rs", "#[derive(Message)] pub struct Beside;"),
+            ("src/private.rs", "#[derive(Message)] pub struct Hidden;"),
+        ];
+
+        let (messages, _) = scan("modules", &files, &[]);
+        assert_eq!(messages, ["inline::nested::Inline", "file::sub::InSub", "file::InFile", "dir::InModRs", "moved::beside::Beside", "moved::Moved"]);
+    }
+
+    #[test]
+    fn only_messages_message_list_can_name_are_registered() {
+        let lib = r#"
+            #[derive(Message)] pub struct Public;
+            #[derive(Clone, Message, Serialize)] pub enum PublicEnum { A }
+            #[derive(Message)] struct Private;
+            #[derive(Message)] pub(crate) struct CrateVisible;
+            #[derive(Message)] pub struct Generic<T> { value: T }
+            #[derive(Clone)] pub struct NotAMessage;
+            pub fn message() {}
+        "#;
+
+        let (messages, warnings) = scan("visibility", &[("src/lib.rs", lib)], &[]);
+        assert_eq!(messages, ["Public", "PublicEnum"]);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn unregistrable_messages_warn() {
+        let files = [
+            ("src/lib.rs", r#"
+                include!(concat!(env!("OUT_DIR"), "/message_registry.rs"));
+                macro_rules! make_message { () => {} }
+                make_message!();
+                pub use other::Message;
+                use other::Private;
+                #[cfg(windows)] pub use other::Windows;
+                pub mod sub;
+            "#),
+            ("src/sub.rs", "other::messages!();"),
+        ];
+
+        let (messages, warnings) = scan("warnings", &files, &[]);
+        assert!(messages.is_empty());
+        let dir = std::env::temp_dir().join(format!("message-registry-warnings-{}", std::process::id())).join("src");
+        assert_eq!(warnings, [
+            format!("Messages made by make_message! in {} aren't registered, list them in message_list! by path", dir.join("lib.rs").display()),
+            format!("Messages re-exported by pub use in {} aren't registered, list them in message_list! by path", dir.join("lib.rs").display()),
+            format!("Messages made by other::messages! in {} (sub) aren't registered, list them in message_list! by path", dir.join("sub.rs").display()),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't find the file for module missing")]
+    fn missing_module_files_fail() {
+        scan("missing-module", &[("src/lib.rs", "pub mod missing;")], &[]);
+    }
+}
@@ -1,14 +1,307 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque}, rc::Rc, sync::Arc};
+
+// Message ids are a hash of the message name, so they stay the same between builds as long as
+// the message isn't renamed or moved. Generic messages are named with the TypePath of their type
+// arguments, which is spelled out by hand, so their ids don't depend on the compiler either.
+pub type MessageId = u64;
+
+// 64 bit FNV-1a
+pub const fn message_id(name: &str) -> MessageId {
+    let bytes = name.as_bytes();
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+#[derive(Debug)]
+pub struct FieldSpec {
+    // None for the fields of tuple structs
+    pub name: Option<&'static str>,
+    // The type as written in the message definition
+    pub ty: &'static str,
+    pub docs: &'static str,
+}
+
+#[derive(Debug)]
+pub struct VariantSpec {
+    pub name: &'static str,
+    pub fields: &'static [FieldSpec],
+    pub docs: &'static str,
+}
+
+#[derive(Debug)]
+pub enum MessageData {
+    Struct(&'static [FieldSpec]),
+    Enum(&'static [VariantSpec]),
+}
+
+// Messages sent through a proxy are queued by priority, see #[pt_priority(...)]. The context always
+// takes the highest priority message first, but lower priorities are never starved completely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    High,
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MessageSpec {
+    pub id: MessageId,
     pub is_async: bool,
+    // true if the message is marked with #[pt_stream(Item)]. The response is a stream of Item,
+    // stream messages are always async.
+    pub is_stream: bool,
+    // true if the message is marked with #[pt_collect(...)]. Any number of handlers can handle the
+    // message, their responses are combined into one.
+    pub is_collect: bool,
+    // true if the message is marked with #[pt_first_match]. The handlers are asked in order until one
+    // responds with Some, the response is None if none of them do.
+    pub is_first_match: bool,
+    // true if the message is marked with #[pt_optional]. The message can have at most one handler,
+    // the response is None if it has none.
+    pub is_optional: bool,
+    // true if the message is marked with #[pt_shared]. Handlers get an Arc of the message, so it is
+    // only copied once however many handlers there are and doesn't need to be Clone.
+    pub is_shared: bool,
+    // For generic messages the name includes the type arguments of the instantiation,
+    // e.g. ::example_messages::Echo<i32>
     pub name: &'static str,
     pub has_response: bool,
+    // false if the message is marked with #[pt_not_during_init], such messages can't be listed in
+    // a handler's #[pt_init(...)]
+    pub available_during_init: bool,
+    // true if the message is marked with #[pt_serde], such messages implement SerdeMessage
+    pub is_serde: bool,
+    // From #[pt_priority(high|normal|low)], Normal if the attribute is missing
+    pub priority: Priority,
+    // From #[pt_timeout(ms)], the default timeout used by try_handle
+    pub timeout: Option<std::time::Duration>,
+
+    // Used to describe the message to tooling, see the message catalog on the context.
+    pub data: MessageData,
+    // The type the sender gets, from #[pt_response(...)] or #[pt_stream(...)] as written in the message
+    // definition. Wrapped in Option<...> for first match and optional messages, and the combined
+    // response for collect messages, e.g. Vec<T> for #[pt_collect(Vec)].
+    pub response_type: Option<&'static str>,
+    pub docs: &'static str,
+    // Every attribute on the message other than doc comments, e.g. "pt_response(i32)"
+    pub attributes: &'static [&'static str],
 }
 
 pub trait Message {
-    // Response is wrapped in a future if the message is async, UnwrappedResponse is not.
+    // Response is wrapped in a future if the message is async, or is a stream of UnwrappedResponse
+    // if the message is a stream.
     type Response<'a>;
     type UnwrappedResponse;
+    // What each handler returns. The same as Response, except for #[pt_collect(...)] messages where
+    // each handler returns one of the values that are combined into the response.
+    type HandlerResponse<'a>;
+    // What each handler is given, the message or an Arc of it for #[pt_shared] messages.
+    type HandlerMessage;
+    // Wraps R the same way Response wraps UnwrappedResponse, i.e. Response<'a> is
+    // Wrapped<'a, UnwrappedResponse>. Used for responses that carry an error, see try_handle.
+    type Wrapped<'a, R>;
+    // is_shared from the spec, as a const so handlers can be checked against it at compile time
+    const IS_SHARED: bool;
+    // available_during_init from the spec, so handlers can be checked against it at compile time too
+    const AVAILABLE_DURING_INIT: bool;
 
     fn get_message_spec() -> &'static MessageSpec;
 }
+
+// Combines the responses of every handler of a #[pt_collect(...)] message into the message's
+// response. The handlers are called in the order they are listed in the context.
+pub trait Collect<T> {
+    type Output;
+
+    fn collect(responses: Vec<T>) -> Self::Output;
+}
+
+// Implemented by the Message derive macro for messages marked with #[pt_collect(...)].
+pub trait CollectMessage: Message {
+    // The pt_response of the message, what each handler responds with
+    type Item;
+    type Collector: Collect<Self::Item, Output = Self::UnwrappedResponse>;
+}
+
+// The reducers for #[pt_collect(Vec)] and #[pt_collect(Sum)]
+pub mod collect {
+    use super::Collect;
+
+    pub struct Vec;
+
+    impl<T> Collect<T> for Vec {
+        type Output = std::vec::Vec<T>;
+
+        fn collect(responses: std::vec::Vec<T>) -> Self::Output {
+            responses
+        }
+    }
+
+    pub struct Sum;
+
+    impl<T: std::iter::Sum> Collect<T> for Sum {
+        type Output = T;
+
+        fn collect(responses: std::vec::Vec<T>) -> Self::Output {
+            responses.into_iter().sum()
+        }
+    }
+}
+
+// Implemented by the Message derive macro for messages marked with #[pt_serde]. Both the message and
+// its response can be serialized, which lets the context encode and decode them.
+pub trait SerdeMessage: Message<UnwrappedResponse: serde::Serialize + serde::de::DeserializeOwned> + serde::Serialize + serde::de::DeserializeOwned {}
+
+// The path of a type as it can be written anywhere, e.g. ::std::string::String. Generic messages
+// and handlers are named with the paths of their type arguments, so every type argument has to
+// implement this. Implement it for your own types with impl_type_path!.
+//
+// The paths are spelled out rather than taken from std::any::type_name, whose output can change
+// between compilers and can name private modules.
+pub trait TypePath {
+    fn type_path() -> String;
+}
+
+// Implements TypePath for types defined in the current module, e.g. impl_type_path!(Point, Line);
+#[macro_export]
+macro_rules! impl_type_path {
+    ($($ty:ident),* $(,)?) => {
+        $(
+            impl $crate::TypePath for $ty {
+                fn type_path() -> ::std::string::String {
+                    ::std::string::String::from(concat!("::", module_path!(), "::", stringify!($ty)))
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_type_path_for {
+    ($($path:literal => $ty:ty),* $(,)?) => {
+        $(
+            impl TypePath for $ty {
+                fn type_path() -> String {
+                    String::from($path)
+                }
+            }
+        )*
+    };
+}
+
+impl_type_path_for!(
+    "bool" => bool, "char" => char, "str" => str, "()" => (),
+    "i8" => i8, "i16" => i16, "i32" => i32, "i64" => i64, "i128" => i128, "isize" => isize,
+    "u8" => u8, "u16" => u16, "u32" => u32, "u64" => u64, "u128" => u128, "usize" => usize,
+    "f32" => f32, "f64" => f64,
+    "::std::string::String" => String,
+    "::std::path::PathBuf" => std::path::PathBuf,
+    "::std::time::Duration" => std::time::Duration,
+);
+
+macro_rules! impl_type_path_for_generic {
+    ($($path:literal => $ty:ident<$($param:ident),+>),* $(,)?) => {
+        $(
+            impl<$($param: TypePath),+> TypePath for $ty<$($param),+> {
+                fn type_path() -> String {
+                    let args: &[String] = &[$($param::type_path()),+];
+                    format!("{}<{}>", $path, args.join(", "))
+                }
+            }
+        )*
+    };
+}
+
+impl_type_path_for_generic!(
+    "::std::vec::Vec" => Vec<T>,
+    "::std::collections::VecDeque" => VecDeque<T>,
+    "::std::option::Option" => Option<T>,
+    "::std::boxed::Box" => Box<T>,
+    "::std::rc::Rc" => Rc<T>,
+    "::std::sync::Arc" => Arc<T>,
+    "::std::collections::HashMap" => HashMap<K, V>,
+    "::std::collections::HashSet" => HashSet<T>,
+    "::std::collections::BTreeMap" => BTreeMap<K, V>,
+    "::std::collections::BTreeSet" => BTreeSet<T>,
+);
+
+macro_rules! impl_type_path_for_tuple {
+    ($(($($param:ident),+)),* $(,)?) => {
+        $(
+            impl<$($param: TypePath),+> TypePath for ($($param,)+) {
+                fn type_path() -> String {
+                    let args: &[String] = &[$($param::type_path()),+];
+                    if args.len() == 1 { format!("({},)", args[0]) } else { format!("({})", args.join(", ")) }
+                }
+            }
+        )*
+    };
+}
+
+impl_type_path_for_tuple!((A), (A, B), (A, B, C), (A, B, C, D));
+
+// Used by the code generated by the Message derive macro.
+pub mod hidden {
+    use std::sync::Mutex;
+
+    pub use serde;
+
+    use super::MessageSpec;
+
+    // A generic message needs a MessageSpec for every instantiation, which can't be put in a static
+    // inside a generic function. Instead they are created the first time they are asked for and leaked.
+    pub fn generic_message_spec(name: String, make_spec: impl FnOnce(&'static str) -> MessageSpec) -> &'static MessageSpec {
+        static SPECS: Mutex<Vec<&'static MessageSpec>> = Mutex::new(Vec::new());
+
+        let mut specs = SPECS.lock().unwrap();
+        if let Some(spec) = specs.iter().find(|spec| spec.name == name) {
+            return spec;
+        }
+
+        let spec: &'static MessageSpec = Box::leak(Box::new(make_spec(Box::leak(name.into_boxed_str()))));
+        specs.push(spec);
+        spec
+    }
+
+    // Replaces the type parameters in ty, a type as written in a generic message, with the paths of
+    // the type arguments of one instantiation, so the spec describes concrete types. Leaked along
+    // with the spec.
+    pub fn substitute_type_params(ty: &str, params: &[&str], args: &[String]) -> &'static str {
+        let mut rtn = String::with_capacity(ty.len());
+        let mut rest = ty;
+
+        while let Some(c) = rest.chars().next() {
+            if c.is_alphanumeric() || c == '_' {
+                let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                let word = &rest[..end];
+                // not if it's part of a path or a lifetime
+                match params.iter().position(|param| *param == word) {
+                    Some(i) if !rtn.ends_with("::") && !rtn.ends_with('\'') => rtn.push_str(&args[i]),
+                    _ => rtn.push_str(word),
+                }
+                rest = &rest[end..];
+            } else {
+                rtn.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        Box::leak(rtn.into_boxed_str())
+    }
+}
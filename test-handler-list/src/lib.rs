@@ -0,0 +1,26 @@
+use context_proc_macros::define_context_type;
+use message_list::messages;
+use application::*;
+
+// A context for the tests in tests/, with the handlers from test_handlers. Every message with a
+// response needs a handler, so it has the example and application handlers too.
+define_context_type!{
+    Messages: messages
+    Handlers: {
+        exit: ExitHandler,
+
+        arithmetic: example_handlers::ArithmeticHandler,
+        init: example_handlers::SomeInitHandler,
+        echo_i32: example_handlers::EchoHandler<i32>,
+        echo_string: example_handlers::EchoHandler<String>,
+        document: example_handlers::Document,
+
+        windows: Windows,
+
+        reentrant: test_handlers::Reentrant,
+        recorder: test_handlers::Recorder,
+        failing: test_handlers::Failing,
+        late_recorder: test_handlers::LateRecorder,
+        waiter: test_handlers::Waiter,
+    }
+}
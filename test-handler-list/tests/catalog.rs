@@ -0,0 +1,51 @@
+use context_structs::serialized::{Json, SerializedMessage};
+use example_messages::{Add1, Echo, EditCommand, NoResponse};
+use jsonschema::{Draft, JSONSchema};
+use message_structs::SerdeMessage;
+use serde_json::{Value, json};
+use test_handler_list::context_type;
+
+context_type!();
+
+fn catalog() -> JSONSchema {
+    let catalog: Value = serde_json::from_str(Context::message_catalog()).unwrap();
+    JSONSchema::options().with_draft(Draft::Draft202012).compile(&catalog).unwrap()
+}
+
+fn serialized<M: SerdeMessage>(message: &M) -> Value
+where Context: context_structs::CtxHandle<M> {
+    let message: SerializedMessage<Json> = Context::encode_message(message).unwrap();
+    serde_json::from_slice(&message.to_bytes().unwrap()).unwrap()
+}
+
+#[test]
+fn serialized_messages_match_the_catalog() {
+    let catalog = catalog();
+    let messages = [
+        serialized(&Add1{ x: 1 }),
+        serialized(&NoResponse{ x: 2 }),
+        serialized(&Echo{ value: 3 }),
+        serialized(&Echo{ value: "three".to_owned() }),
+        serialized(&EditCommand::Insert{ at: 0, text: "hello".to_owned() }),
+        serialized(&EditCommand::Clear),
+    ];
+    for message in messages {
+        assert!(catalog.is_valid(&message), "{} doesn't match the catalog", message);
+    }
+}
+
+#[test]
+fn other_values_do_not_match_the_catalog() {
+    let catalog = catalog();
+    let mut wrong_payload = serialized(&Add1{ x: 1 });
+    wrong_payload["payload"] = json!({"x": "one"});
+    // Echo<String> under the id of Echo<i32>
+    let mut wrong_id = serialized(&Echo{ value: "three".to_owned() });
+    wrong_id["id"] = serialized(&Echo{ value: 3 })["id"].clone();
+    let unknown_id = json!({"id": "1", "payload": {"x": 1}});
+    let no_payload = json!({"id": serialized(&Add1{ x: 1 })["id"]});
+
+    for message in [wrong_payload, wrong_id, unknown_id, no_payload] {
+        assert!(!catalog.is_valid(&message), "{} matches the catalog", message);
+    }
+}
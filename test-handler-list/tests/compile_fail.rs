@@ -0,0 +1,7 @@
+// Handlers that the macros reject, with the errors in tests/ui/*.stderr. Run with
+// TRYBUILD=overwrite to update them.
+#[test]
+fn rejected_handlers() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,31 @@
+use context_structs::config::ConfigTable;
+use smol::future;
+use test_handler_list::context_type;
+use test_handlers::take_log;
+
+context_type!();
+
+#[test]
+fn failed_init_shuts_down_the_initialised_handlers() {
+    let mut config: ContextConfig = ConfigTable::default().get().unwrap();
+    config.failing.fail = true;
+
+    let error = future::block_on(Context::new(config)).err().unwrap();
+    assert_eq!(error.handler, "failing");
+    // the first wave is initialised, and shut down in reverse, except for the handler that failed.
+    // The second wave isn't started, so Recorder's request to arithmetic fails.
+    assert_eq!(take_log(), [
+        "recorder init",
+        "failing init",
+        "recorder shutdown, Add1 failed: the handler of the message wasn't initialised",
+    ]);
+}
+
+#[test]
+fn init_runs_in_waves() {
+    let config: ContextConfig = ConfigTable::default().get().unwrap();
+
+    let context = future::block_on(Context::new(config)).unwrap();
+    assert_eq!(take_log(), ["recorder init", "failing init", "late recorder init, got 42"]);
+    drop(context);
+}
@@ -0,0 +1,48 @@
+use context_structs::{HandleError, config::ConfigTable};
+use example_messages::{Add1, FindInDocument, Times3};
+use futures::StreamExt;
+use message_list::C;
+use smol::future;
+use test_handler_list::context_type;
+
+context_type!();
+
+fn new_context() -> Context {
+    let config = ConfigTable::default().get().unwrap();
+    future::block_on(Context::new(config)).unwrap()
+}
+
+// Nothing takes messages off the queue until the context runs on this thread, so waiting here
+// would never end
+#[test]
+fn requests_before_the_context_runs_fail_on_its_thread() {
+    let context = new_context();
+    let proxy = context.proxy();
+
+    assert_eq!(proxy.try_handle(Add1{ x: 1 }), Err(HandleError::NotRunning));
+    assert_eq!(future::block_on(proxy.try_handle(Times3{ x: 1 })), Err(HandleError::NotRunning));
+    let items: Vec<_> = future::block_on(proxy.try_handle(FindInDocument{ pattern: "a".to_owned() }).collect());
+    assert_eq!(items, [Err(HandleError::NotRunning)]);
+}
+
+#[test]
+#[should_panic(expected = "the context isn't running yet, and this is the thread that runs it")]
+fn handle_before_the_context_runs_panics_on_its_thread() {
+    let context = new_context();
+
+    context.proxy().handle(Add1{ x: 1 });
+}
+
+#[test]
+fn other_threads_wait_for_the_context_to_run() {
+    let context = new_context();
+    let proxy = context.proxy();
+
+    let thread = std::thread::spawn(move || {
+        let responses = (proxy.try_handle(Add1{ x: 1 }), future::block_on(proxy.try_handle(Times3{ x: 2 })));
+        proxy.quit();
+        responses
+    });
+    future::block_on(context.run());
+    assert_eq!(thread.join().unwrap(), (Ok(2), Ok(6)));
+}
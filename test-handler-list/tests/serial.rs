@@ -0,0 +1,31 @@
+use context_structs::{CtxHandle, config::ConfigTable};
+use example_messages::NoResponse;
+use smol::future;
+use test_handler_list::context_type;
+use test_handlers::{RESEND, take_log};
+
+context_type!();
+
+fn new_context() -> Context {
+    let config = ConfigTable::default().get().unwrap();
+    future::block_on(Context::new(config)).unwrap()
+}
+
+#[test]
+fn serial_handler_handles_messages_in_turn() {
+    let context = new_context();
+    take_log();
+
+    future::block_on(async {
+        futures::join!(context.handle(NoResponse{ x: 1 }), context.handle(NoResponse{ x: 2 }));
+    });
+    assert_eq!(take_log(), ["reentrant 1 got 1", "reentrant 2 got 2"]);
+}
+
+#[test]
+#[should_panic(expected = "Serial handler ::test_handlers::Reentrant sent a message that came back to it")]
+fn serial_handler_sending_itself_a_message_panics() {
+    let context = new_context();
+
+    future::block_on(context.handle(NoResponse{ x: RESEND }));
+}
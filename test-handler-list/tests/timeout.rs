@@ -0,0 +1,81 @@
+use std::{thread, time::Duration};
+
+use context_structs::{CtxPost, CtxTryHandle, HandleError, config::ConfigTable};
+use example_messages::{Add1, Wait};
+use message_list::C;
+use smol::future;
+use test_handler_list::context_type;
+use test_handlers::take_log;
+
+context_type!();
+
+fn new_context() -> Context {
+    let config = ConfigTable::default().get().unwrap();
+    future::block_on(Context::new(config)).unwrap()
+}
+
+// Without the init and shutdown of the other handlers
+fn waiter_log() -> Vec<String> {
+    take_log().into_iter().filter(|entry| entry.starts_with("waiter")).collect()
+}
+
+#[test]
+fn handlers_are_cancelled_at_the_deadline() {
+    let context = new_context();
+
+    assert_eq!(future::block_on(context.try_handle(Wait{ ms: 1000 })), Err(HandleError::TimedOut));
+    assert_eq!(future::block_on(context.try_handle(Wait{ ms: 10 })), Ok(()));
+    assert_eq!(waiter_log(), ["waiter waiting 1000", "waiter dropped 1000", "waiter waiting 10", "waiter done 10", "waiter dropped 10"]);
+}
+
+// The context cancels the handler itself, rather than leaving it to run after the proxy gave up
+#[test]
+fn handlers_are_cancelled_at_the_deadline_through_the_proxy() {
+    let context = new_context();
+    let proxy = context.proxy();
+
+    let thread = thread::spawn(move || {
+        let timed_out = future::block_on(proxy.try_handle(Wait{ ms: 1000 }));
+        thread::sleep(Duration::from_millis(50));
+        let done = future::block_on(proxy.try_handle(Wait{ ms: 10 }));
+        proxy.quit();
+        (timed_out, done)
+    });
+    future::block_on(context.run());
+    assert_eq!(thread.join().unwrap(), (Err(HandleError::TimedOut), Ok(())));
+    assert_eq!(waiter_log(), ["waiter waiting 1000", "waiter dropped 1000", "waiter waiting 10", "waiter done 10", "waiter dropped 10"]);
+}
+
+// Wait's 100ms would be long gone if they counted from when it was posted
+#[test]
+fn posted_messages_time_out_from_when_the_context_takes_them() {
+    let context = new_context();
+    let proxy = context.proxy();
+
+    context.post(Wait{ ms: 10 }).unwrap();
+    thread::sleep(Duration::from_millis(150));
+    let thread = thread::spawn(move || {
+        let done = future::block_on(proxy.try_handle(Wait{ ms: 50 }));
+        proxy.quit();
+        done
+    });
+    future::block_on(context.run());
+    assert_eq!(thread.join().unwrap(), Ok(()));
+    assert_eq!(waiter_log(), ["waiter waiting 10", "waiter waiting 50", "waiter done 10", "waiter dropped 10", "waiter done 50", "waiter dropped 50"]);
+}
+
+// A sync request through the proxy stops waiting at its deadline, even though the context hasn't
+// run to answer it
+#[test]
+fn sync_proxy_requests_time_out() {
+    let context = new_context();
+    let proxy = context.proxy();
+
+    let thread = thread::spawn(move || {
+        let timed_out = proxy.handle_with_timeout(Add1{ x: 1 }, Duration::from_millis(50));
+        proxy.quit();
+        timed_out
+    });
+    assert_eq!(thread.join().unwrap(), Err(HandleError::TimedOut));
+    future::block_on(context.run());
+}
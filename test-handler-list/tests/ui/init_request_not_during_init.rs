@@ -0,0 +1,16 @@
+use application_messages::KeyPress;
+use context_structs::CtxHandle;
+use handler_proc_macros::Agent;
+use message_list::C;
+
+// KeyPress only comes once the context runs, so it can't be waited for during init
+pub struct WaitsForKey {}
+
+#[Agent]
+impl WaitsForKey {
+    fn init(_ctx: &impl CtxHandle<KeyPress>) -> Self {
+        Self {}
+    }
+}
+
+fn main() {}
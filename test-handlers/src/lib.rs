@@ -0,0 +1,140 @@
+use std::{cell::RefCell, error::Error, fmt::Display, time::Duration};
+
+use context_structs::CtxHandle;
+use example_messages::{Add1, GetExampleInitValue, NoResponse, Wait};
+use handler_proc_macros::Agent;
+use message_list::C;
+use serde::{Deserialize, Serialize};
+
+// Handlers for the tests of the context in test-handler-list. They write what they do to a log,
+// kept per thread so the tests can run in parallel.
+
+thread_local! {
+    static LOG: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn log(entry: String) {
+    LOG.with_borrow_mut(|log| log.push(entry));
+}
+
+// What the handlers have done on this thread since the log was last taken
+pub fn take_log() -> Vec<String> {
+    LOG.with_borrow_mut(std::mem::take)
+}
+
+
+// NoResponse with this x makes Reentrant send NoResponse to the context, which comes back to it
+pub const RESEND: i32 = -1;
+
+// Serial, so sending a message it handles while handling one would wait for itself
+#[derive(Default)]
+pub struct Reentrant {
+    count: usize,
+}
+
+#[Agent]
+impl Reentrant {
+    async fn no_response(&mut self, ctx: &impl C, message: NoResponse) {
+        self.count += 1;
+        log(format!("reentrant {} got {}", self.count, message.x));
+        if message.x == RESEND {
+            ctx.handle(NoResponse{ x: 0 }).await;
+        }
+    }
+}
+
+
+// Logs when it starts and finishes waiting, and when the wait is dropped, whether it finished or
+// was cancelled
+#[derive(Default)]
+pub struct Waiter {}
+
+struct LogOnDrop(String);
+
+impl Drop for LogOnDrop {
+    fn drop(&mut self) {
+        log(std::mem::take(&mut self.0));
+    }
+}
+
+#[Agent]
+impl Waiter {
+    async fn wait(&self, message: Wait) {
+        log(format!("waiter waiting {}", message.ms));
+        let _dropped = LogOnDrop(format!("waiter dropped {}", message.ms));
+        smol::Timer::after(Duration::from_millis(message.ms)).await;
+        log(format!("waiter done {}", message.ms));
+    }
+}
+
+
+// Fails to initialise if its config says so. It's in the first wave of init along with Recorder.
+#[derive(Serialize, Deserialize, Default)]
+pub struct FailingConfig {
+    pub fail: bool,
+}
+
+#[derive(Debug)]
+pub struct InitFailed;
+
+impl Display for InitFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "told to fail")
+    }
+}
+
+impl Error for InitFailed {}
+
+pub struct Failing {}
+
+#[Agent]
+impl Failing {
+    fn init(config: FailingConfig) -> Result<Self, InitFailed> {
+        log("failing init".to_string());
+        if config.fail {
+            Err(InitFailed)
+        } else {
+            Ok(Self {})
+        }
+    }
+
+    async fn shutdown(&self) {
+        log("failing shutdown".to_string());
+    }
+}
+
+
+// Asks for Add1 when it's shut down. Its handler is only initialised after the first wave, as it
+// needs GetExampleInitValue.
+pub struct Recorder {}
+
+#[Agent]
+impl Recorder {
+    fn init() -> Self {
+        log("recorder init".to_string());
+        Self {}
+    }
+
+    async fn shutdown(&self, ctx: &impl C) {
+        match ctx.try_handle(Add1{ x: 1 }) {
+            Ok(response) => log(format!("recorder shutdown, Add1 gave {}", response)),
+            Err(e) => log(format!("recorder shutdown, Add1 failed: {}", e)),
+        }
+    }
+}
+
+
+// In the second wave of init, as it needs GetExampleInitValue
+pub struct LateRecorder {}
+
+#[Agent]
+impl LateRecorder {
+    async fn init(ctx: &impl CtxHandle<GetExampleInitValue>) -> Self {
+        log(format!("late recorder init, got {}", ctx.handle(GetExampleInitValue{}).await));
+        Self {}
+    }
+
+    async fn shutdown(&self) {
+        log("late recorder shutdown".to_string());
+    }
+}